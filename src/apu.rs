@@ -0,0 +1,970 @@
+use crate::{
+    audio::{self, AudioConfig, AudioOutput, Side},
+    mapper::Mapper,
+    region::Region,
+};
+
+// https://wiki.nesdev.com/w/index.php/APU
+#[derive(Debug, Default)]
+pub struct APU {
+    region: Region,
+    pulse1: Pulse,               // $4000-$4003
+    pulse2: Pulse,               // $4004-$4007
+    triangle: Triangle,          // $4008-$400B
+    noise: Noise,                // $400C-$400F
+    dmc: DMC,                    // $4010-$4013
+    frame_counter: FrameCounter, // $4017
+    config: AudioConfig,
+    output: AudioOutput,       // mono, or the left side in stereo
+    right_output: AudioOutput, // only used in stereo
+    cycles: usize,
+}
+
+impl APU {
+    pub fn new() -> Self {
+        Self {
+            pulse1: Pulse::new(SweepNegate::OnesComplement),
+            pulse2: Pulse::new(SweepNegate::TwosComplement),
+            ..Self::default()
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.output.set_clock_rate(region.cpu_clock_rate());
+        self.right_output.set_clock_rate(region.cpu_clock_rate());
+    }
+
+    // output sample rate in Hz, e.g. 44100 or 48000
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.output.set_sample_rate(sample_rate);
+        self.right_output.set_sample_rate(sample_rate);
+    }
+
+    pub fn audio_config(&self) -> AudioConfig {
+        self.config
+    }
+
+    pub fn set_audio_config(&mut self, config: AudioConfig) {
+        // pending samples are in the old layout
+        if config.stereo != self.config.stereo {
+            // the right channel isn't clocked in mono, so it picks up where the mono mix
+            // left off instead of stepping from a stale level
+            if config.stereo {
+                self.right_output = self.output.clone();
+            }
+            self.output.discard_samples();
+            self.right_output.discard_samples();
+        }
+        self.config = config;
+    }
+
+    // appends the samples mixed since the last call, interleaved left and right in stereo
+    pub fn take_audio(&mut self, out: &mut Vec<i16>) {
+        if !self.config.stereo {
+            self.output.take_samples(out);
+            return;
+        }
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        self.output.take_samples(&mut left);
+        self.right_output.take_samples(&mut right);
+        for (left, right) in left.into_iter().zip(right) {
+            out.push(left);
+            out.push(right);
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/APU#Status_.28.244015.29
+    // the reset button silences every channel, as if $4015 were written with 0
+    // the frame counter keeps its mode and restarts its sequence
+    pub fn reset(&mut self) {
+        self.write_byte(0x4015, 0);
+        self.frame_counter.interrupt = false;
+        self.frame_counter.cycles = 0;
+    }
+
+    // called once per CPU cycle, the mapper's sound chip is clocked and mixed in too
+    pub fn step(&mut self, mapper: &mut Mapper) {
+        // pulse timers are clocked every other CPU cycle (once per APU cycle)
+        if self.cycles % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer(self.region);
+        self.dmc.clock_timer(self.region);
+        let (quarter, half) = self.frame_counter.clock(self.region);
+        if quarter {
+            self.clock_quarter_frame();
+        }
+        if half {
+            self.clock_half_frame();
+        }
+        mapper.clock_audio();
+        let levels = self.channel_outputs();
+        let expansion = mapper.audio_output();
+        if self.config.stereo {
+            let left = audio::mix(self.config.apply(levels, Some(Side::Left)))
+                + self.config.apply_expansion(expansion, Some(Side::Left));
+            let right = audio::mix(self.config.apply(levels, Some(Side::Right)))
+                + self.config.apply_expansion(expansion, Some(Side::Right));
+            self.output.clock(left);
+            self.right_output.clock(right);
+        } else {
+            self.output.clock(
+                audio::mix(self.config.apply(levels, None))
+                    + self.config.apply_expansion(expansion, None),
+            );
+        }
+        self.cycles += 1;
+    }
+
+    // current level of pulse 1, pulse 2, triangle and noise (4 bits) and DMC (7 bits)
+    pub fn channel_outputs(&self) -> [u8; 5] {
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output_level,
+        ]
+    }
+
+    // https://wiki.nesdev.com/w/index.php/APU_DMC#Memory_reader
+    // address of the sample byte to fetch, when the sample buffer is empty
+    pub fn dmc_dma_request(&self) -> Option<usize> {
+        if self.dmc.sample_buffer.is_none() && self.dmc.bytes_remaining > 0 {
+            Some(self.dmc.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn dmc_dma_write(&mut self, byte: u8) {
+        self.dmc.fill_sample_buffer(byte);
+    }
+
+    // level of the IRQ line
+    pub fn irq(&self) -> bool {
+        self.dmc.interrupt || self.frame_counter.interrupt
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.linear_counter.clock();
+        self.noise.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.length_counter.clock();
+        self.noise.length_counter.clock();
+    }
+
+    // https://wiki.nesdev.com/w/index.php/APU#Status_.28.244015.29
+    // IF.D NT21: DMC interrupt, frame interrupt, then whether each channel is still playing
+    fn status(&self) -> u8 {
+        u8::from(self.dmc.interrupt) << 7
+            | u8::from(self.frame_counter.interrupt) << 6
+            | u8::from(self.dmc.bytes_remaining > 0) << 4
+            | u8::from(!self.noise.length_counter.is_silenced()) << 3
+            | u8::from(!self.triangle.length_counter.is_silenced()) << 2
+            | u8::from(!self.pulse2.length_counter.is_silenced()) << 1
+            | u8::from(!self.pulse1.length_counter.is_silenced())
+    }
+
+    pub fn read_byte(&mut self, address: usize) -> u8 {
+        match address {
+            // reading acknowledges the frame interrupt, but not the DMC interrupt
+            0x4015 => {
+                let byte = self.status();
+                self.frame_counter.interrupt = false;
+                byte
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn read_byte_without_effect(&self, address: usize) -> u8 {
+        match address {
+            0x4015 => self.status(),
+            _ => 0,
+        }
+    }
+
+    pub fn write_byte(&mut self, address: usize, byte: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.write_byte(address - 0x4000, byte),
+            0x4004..=0x4007 => self.pulse2.write_byte(address - 0x4004, byte),
+            0x4008..=0x400B => self.triangle.write_byte(address - 0x4008, byte),
+            0x400C..=0x400F => self.noise.write_byte(address - 0x400C, byte),
+            0x4010..=0x4013 => self.dmc.write_byte(address - 0x4010, byte),
+            0x4015 => {
+                self.pulse1.length_counter.set_enabled(byte & 0b0001 > 0);
+                self.pulse2.length_counter.set_enabled(byte & 0b0010 > 0);
+                self.triangle.length_counter.set_enabled(byte & 0b0100 > 0);
+                self.noise.length_counter.set_enabled(byte & 0b1000 > 0);
+                self.dmc.set_enabled(byte & 0b10000 > 0);
+            }
+            0x4017 => self.frame_counter.write_byte(byte, self.cycles % 2 == 1),
+            _ => {}
+        }
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+// CPU cycles after the start of the sequence at which each step is clocked,
+// the 4-step sequence stops after the fourth
+const NTSC_FRAME_STEPS: [usize; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [usize; 5] = [8313, 16627, 24939, 33253, 41565];
+
+#[derive(Debug, Default)]
+struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    interrupt: bool,
+    cycles: usize,
+    reset_delay: Option<usize>, // CPU cycles until a $4017 write restarts the sequence
+}
+
+impl FrameCounter {
+    // MI.. ....
+    // the sequence restarts 3 CPU cycles after the write, or 4 when written between APU cycles
+    fn write_byte(&mut self, byte: u8, odd_cycle: bool) {
+        self.five_step = byte & 0b10000000 > 0;
+        self.irq_inhibit = byte & 0b01000000 > 0;
+        if self.irq_inhibit {
+            self.interrupt = false;
+        }
+        self.reset_delay = Some(if odd_cycle { 4 } else { 3 });
+    }
+
+    // returns whether this cycle clocks a quarter frame and a half frame
+    fn clock(&mut self, region: Region) -> (bool, bool) {
+        if let Some(delay) = self.reset_delay {
+            if delay <= 1 {
+                self.reset_delay = None;
+                self.cycles = 0;
+                // selecting the 5-step sequence clocks everything immediately
+                return (self.five_step, self.five_step);
+            }
+            self.reset_delay = Some(delay - 1);
+        }
+
+        let steps = match region {
+            Region::Pal => &PAL_FRAME_STEPS,
+            Region::Ntsc | Region::Dendy => &NTSC_FRAME_STEPS,
+        };
+        self.cycles += 1;
+        // the flag is raised on 3 consecutive cycles around the last step
+        if !self.five_step
+            && !self.irq_inhibit
+            && (steps[3] - 1..=steps[3] + 1).contains(&self.cycles)
+        {
+            self.interrupt = true;
+        }
+        let clocks = match steps.iter().position(|&cycle| cycle == self.cycles) {
+            Some(0) | Some(2) => (true, false),
+            Some(1) | Some(4) => (true, true),
+            Some(3) if !self.five_step => (true, true),
+            _ => (false, false),
+        };
+        let last = if self.five_step { steps[4] } else { steps[3] };
+        if self.cycles > last {
+            self.cycles = 0;
+        }
+        clocks
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Length_Counter
+static LENGTH_TABLE: [u8; 0x20] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, //
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Debug, Default)]
+struct LengthCounter {
+    enabled: bool, // $4015
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // index: the upper 5 bits of the channel's last register
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[index as usize & 0x1F];
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn is_silenced(&self) -> bool {
+        self.counter == 0
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Envelope
+#[derive(Debug, Default)]
+struct Envelope {
+    start: bool,
+    looping: bool, // shares its bit with the length counter halt flag
+    constant_volume: bool,
+    volume: u8, // constant volume, or the divider period
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // ..LC VVVV
+    fn set_as_u8(&mut self, byte: u8) {
+        self.looping = byte & 0b00100000 > 0;
+        self.constant_volume = byte & 0b00010000 > 0;
+        self.volume = byte & 0b00001111;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+            return;
+        }
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+        self.divider = self.volume;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looping {
+            self.decay = 15;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+// pulse 1 subtracts one more than pulse 2 when sweeping down
+#[derive(Debug, Clone, Copy)]
+enum SweepNegate {
+    OnesComplement,
+    TwosComplement,
+}
+
+impl Default for SweepNegate {
+    fn default() -> Self {
+        Self::TwosComplement
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Sweep
+#[derive(Debug, Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+    negate_mode: SweepNegate,
+}
+
+impl Sweep {
+    // EPPP NSSS
+    fn set_as_u8(&mut self, byte: u8) {
+        self.enabled = byte & 0b10000000 > 0;
+        self.period = (byte & 0b01110000) >> 4;
+        self.negate = byte & 0b00001000 > 0;
+        self.shift = byte & 0b00000111;
+        self.reload = true;
+    }
+
+    // the target period is computed continuously, even while the sweep is disabled
+    fn target_period(&self, timer_period: u16) -> u16 {
+        let change = timer_period >> self.shift;
+        if !self.negate {
+            return timer_period + change;
+        }
+        match self.negate_mode {
+            SweepNegate::OnesComplement => timer_period.saturating_sub(change + 1),
+            SweepNegate::TwosComplement => timer_period.saturating_sub(change),
+        }
+    }
+
+    fn is_muting(&self, timer_period: u16) -> bool {
+        timer_period < 8 || self.target_period(timer_period) > 0x7FF
+    }
+
+    // returns the new timer period
+    fn clock(&mut self, timer_period: u16) -> u16 {
+        let mut period = timer_period;
+        if self.divider == 0 && self.enabled && self.shift > 0 && !self.is_muting(timer_period) {
+            period = self.target_period(timer_period);
+        }
+        if self.divider == 0 || self.reload {
+            self.divider = self.period;
+            self.reload = false;
+        } else {
+            self.divider -= 1;
+        }
+        period
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Pulse
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+#[derive(Debug, Default)]
+struct Pulse {
+    duty: u8,
+    sequence: usize,
+    timer_period: u16, // 11 bits
+    timer: u16,
+    envelope: Envelope,
+    sweep: Sweep,
+    length_counter: LengthCounter,
+}
+
+impl Pulse {
+    fn new(negate_mode: SweepNegate) -> Self {
+        let mut pulse = Self::default();
+        pulse.sweep.negate_mode = negate_mode;
+        pulse
+    }
+
+    // address: 0-3, relative to the channel's first register
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        match address {
+            // DDLC VVVV
+            0 => {
+                self.duty = byte >> 6;
+                self.envelope.set_as_u8(byte);
+                self.length_counter.halt = byte & 0b00100000 > 0;
+            }
+            1 => self.sweep.set_as_u8(byte),
+            2 => self.timer_period = (self.timer_period & 0x700) | byte as u16,
+            // LLLL LHHH
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | ((byte as u16 & 0b111) << 8);
+                self.length_counter.load(byte >> 3);
+                self.sequence = 0;
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence = (self.sequence + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        self.timer_period = self.sweep.clock(self.timer_period);
+    }
+
+    fn output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.sequence] == 0
+            || self.length_counter.is_silenced()
+            || self.sweep.is_muting(self.timer_period)
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Triangle
+static TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, //
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Default)]
+struct LinearCounter {
+    control: bool, // shares its bit with the length counter halt flag
+    reload_value: u8,
+    reload: bool,
+    counter: u8,
+}
+
+impl LinearCounter {
+    fn clock(&mut self) {
+        if self.reload {
+            self.counter = self.reload_value;
+        } else if self.counter > 0 {
+            self.counter -= 1;
+        }
+        if !self.control {
+            self.reload = false;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Triangle {
+    sequence: usize,
+    timer_period: u16, // 11 bits
+    timer: u16,
+    linear_counter: LinearCounter,
+    length_counter: LengthCounter,
+}
+
+impl Triangle {
+    // address: 0-3, relative to $4008
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        match address {
+            // CRRR RRRR
+            0 => {
+                self.linear_counter.control = byte & 0b10000000 > 0;
+                self.linear_counter.reload_value = byte & 0b01111111;
+                self.length_counter.halt = byte & 0b10000000 > 0;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | byte as u16,
+            // LLLL LHHH
+            3 => {
+                self.timer_period = (self.timer_period & 0xFF) | ((byte as u16 & 0b111) << 8);
+                self.length_counter.load(byte >> 3);
+                self.linear_counter.reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    // clocked every CPU cycle, twice as fast as the pulse timers
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period;
+        // periods below 2 are far above hearing, and emulating them only adds noise
+        if self.linear_counter.counter > 0
+            && !self.length_counter.is_silenced()
+            && self.timer_period >= 2
+        {
+            self.sequence = (self.sequence + 1) % 32;
+        }
+    }
+
+    // the sequencer holds its level when halted, it is never forced to 0
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequence]
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Noise
+// timer periods in CPU cycles
+static NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+static PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Debug)]
+struct Noise {
+    short_mode: bool, // feedback from bit 6 instead of bit 1, 93 or 31 steps long
+    period_index: u8,
+    timer: u16,
+    shift_register: u16, // 15 bits
+    envelope: Envelope,
+    length_counter: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            short_mode: false,
+            period_index: 0,
+            timer: 0,
+            // loaded with 1 on power-up
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+}
+
+impl Noise {
+    // address: 0-3, relative to $400C
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        match address {
+            // ..LC VVVV
+            0 => {
+                self.envelope.set_as_u8(byte);
+                self.length_counter.halt = byte & 0b00100000 > 0;
+            }
+            // M... PPPP
+            2 => {
+                self.short_mode = byte & 0b10000000 > 0;
+                self.period_index = byte & 0b00001111;
+            }
+            // LLLL L...
+            3 => {
+                self.length_counter.load(byte >> 3);
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    // clocked every CPU cycle
+    fn clock_timer(&mut self, region: Region) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        let periods = match region {
+            Region::Pal => &PAL_NOISE_PERIODS,
+            Region::Ntsc | Region::Dendy => &NTSC_NOISE_PERIODS,
+        };
+        self.timer = periods[self.period_index as usize] - 1;
+
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || self.length_counter.is_silenced() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_DMC
+// timer periods in CPU cycles
+static NTSC_DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+static PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+#[derive(Debug)]
+struct DMC {
+    irq_enabled: bool,
+    looping: bool,
+    rate_index: u8,
+    timer: u16,
+    interrupt: bool,
+
+    // memory reader
+    sample_address: usize, // $C000-$FFC0
+    sample_length: usize,  // 1-4081 bytes
+    current_address: usize,
+    bytes_remaining: usize,
+    sample_buffer: Option<u8>,
+
+    // output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8, // 7 bits
+}
+
+impl Default for DMC {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            looping: false,
+            rate_index: 0,
+            timer: 0,
+            interrupt: false,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
+        }
+    }
+}
+
+impl DMC {
+    // address: 0-3, relative to $4010
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        match address {
+            // IL.. RRRR
+            0 => {
+                self.irq_enabled = byte & 0b10000000 > 0;
+                self.looping = byte & 0b01000000 > 0;
+                self.rate_index = byte & 0b00001111;
+                if !self.irq_enabled {
+                    self.interrupt = false;
+                }
+            }
+            // .DDD DDDD
+            1 => self.output_level = byte & 0b01111111,
+            // %11AAAAAA.AA000000
+            2 => self.sample_address = 0xC000 + byte as usize * 64,
+            // %LLLL.LLLL0001
+            3 => self.sample_length = byte as usize * 16 + 1,
+            _ => {}
+        }
+    }
+
+    // $4015 bit 4, which also acknowledges the DMC interrupt
+    fn set_enabled(&mut self, enabled: bool) {
+        self.interrupt = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fill_sample_buffer(&mut self, byte: u8) {
+        self.sample_buffer = Some(byte);
+        // the address wraps around to $8000, not $0000
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.interrupt = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    fn clock_timer(&mut self, region: Region) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        let rates = match region {
+            Region::Pal => &PAL_DMC_RATES,
+            Region::Ntsc | Region::Dendy => &NTSC_DMC_RATES,
+        };
+        self.timer = rates[self.rate_index as usize] - 1;
+
+        if !self.silence {
+            // the level moves by 2 and stays within 0-127
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift_register = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::{Mirroring, NRomMapper};
+
+    // cycles, counted from the start of the sequence, that clock quarter and half frames
+    fn frame_clocks(frame_counter: &mut FrameCounter, cycles: usize) -> (Vec<usize>, Vec<usize>) {
+        let (mut quarters, mut halves) = (Vec::new(), Vec::new());
+        for cycle in 1..=cycles {
+            let (quarter, half) = frame_counter.clock(Region::Ntsc);
+            if quarter {
+                quarters.push(cycle);
+            }
+            if half {
+                halves.push(cycle);
+            }
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn four_step_sequence() {
+        let mut frame_counter = FrameCounter::default();
+        let (quarters, halves) = frame_clocks(&mut frame_counter, 29830);
+        assert_eq!(quarters, [7457, 14913, 22371, 29829]);
+        assert_eq!(halves, [14913, 29829]);
+    }
+
+    #[test]
+    fn five_step_sequence() {
+        let mut frame_counter = FrameCounter {
+            five_step: true,
+            ..FrameCounter::default()
+        };
+        let (quarters, halves) = frame_clocks(&mut frame_counter, 37282);
+        assert_eq!(quarters, [7457, 14913, 22371, 37281]);
+        assert_eq!(halves, [14913, 37281]);
+        assert!(!frame_counter.interrupt);
+    }
+
+    #[test]
+    fn frame_irq_is_raised_for_three_cycles() {
+        let mut frame_counter = FrameCounter::default();
+        let mut raised = Vec::new();
+        for cycle in 1..=29832 {
+            frame_counter.clock(Region::Ntsc);
+            if frame_counter.interrupt {
+                raised.push(cycle);
+                // acknowledged every cycle, so only the cycles that set it are seen
+                frame_counter.interrupt = false;
+            }
+        }
+        assert_eq!(raised, [29828, 29829, 29830]);
+    }
+
+    #[test]
+    fn frame_irq_inhibit() {
+        let mut frame_counter = FrameCounter::default();
+        frame_clocks(&mut frame_counter, 29829);
+        assert!(frame_counter.interrupt);
+        frame_counter.write_byte(0b01000000, false);
+        assert!(!frame_counter.interrupt);
+        frame_clocks(&mut frame_counter, 29830 * 2);
+        assert!(!frame_counter.interrupt);
+    }
+
+    #[test]
+    fn write_restarts_the_sequence_after_three_or_four_cycles() {
+        for &(odd_cycle, delay) in &[(false, 3), (true, 4)] {
+            let mut frame_counter = FrameCounter::default();
+            frame_clocks(&mut frame_counter, 1000);
+            frame_counter.write_byte(0, odd_cycle);
+            let mut cycles = 0;
+            while frame_counter.reset_delay.is_some() {
+                frame_counter.clock(Region::Ntsc);
+                cycles += 1;
+            }
+            assert_eq!(cycles, delay);
+            assert_eq!(frame_counter.cycles, 0);
+            let (quarters, _) = frame_clocks(&mut frame_counter, 7457);
+            assert_eq!(quarters, [7457]);
+        }
+    }
+
+    #[test]
+    fn selecting_five_steps_clocks_immediately() {
+        for &(byte, clocks) in &[(0b10000000, (true, true)), (0, (false, false))] {
+            let mut frame_counter = FrameCounter::default();
+            frame_counter.write_byte(byte, false);
+            frame_counter.clock(Region::Ntsc);
+            frame_counter.clock(Region::Ntsc);
+            assert_eq!(frame_counter.clock(Region::Ntsc), clocks);
+        }
+    }
+
+    #[test]
+    fn stereo_starts_from_the_mono_level() {
+        let mut apu = APU::new();
+        let mut mapper = Mapper::NRom(NRomMapper::new(1, Mirroring::Horizontal));
+        apu.write_byte(0x4015, 0b01000);
+        apu.write_byte(0x400C, 0b00111111); // constant volume 15
+        apu.write_byte(0x400F, 0);
+        for _ in 0..10000 {
+            apu.step(&mut mapper);
+        }
+        apu.set_audio_config(AudioConfig {
+            stereo: true,
+            ..apu.audio_config()
+        });
+        for _ in 0..1000 {
+            apu.step(&mut mapper);
+        }
+        let mut samples = Vec::new();
+        apu.take_audio(&mut samples);
+        // noise panned to the centre is the same on both sides
+        for pair in samples.chunks(2) {
+            assert_eq!(pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn length_counter() {
+        let mut length_counter = LengthCounter::default();
+        length_counter.load(1);
+        assert!(length_counter.is_silenced());
+
+        length_counter.set_enabled(true);
+        length_counter.load(1);
+        assert_eq!(length_counter.counter, 254);
+        length_counter.load(0);
+        assert_eq!(length_counter.counter, 10);
+        length_counter.clock();
+        assert_eq!(length_counter.counter, 9);
+
+        length_counter.halt = true;
+        length_counter.clock();
+        assert_eq!(length_counter.counter, 9);
+
+        length_counter.set_enabled(false);
+        assert!(length_counter.is_silenced());
+    }
+
+    #[test]
+    fn pulse_1_sweeps_down_one_further_than_pulse_2() {
+        let mut apu = APU::new();
+        for &base in &[0x4000, 0x4004] {
+            apu.write_byte(0x4015, 0b11);
+            apu.write_byte(base + 1, 0b10001001); // enabled, negate, shift 1
+            apu.write_byte(base + 2, 0x00);
+            apu.write_byte(base + 3, 0x01); // period $100
+        }
+        let target = |pulse: &Pulse| pulse.sweep.target_period(pulse.timer_period);
+        assert_eq!(target(&apu.pulse1), 0x7F);
+        assert_eq!(target(&apu.pulse2), 0x80);
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::VecDeque;
+
+use crate::{mapper::ExpansionChip, region::Region};
+
+// https://wiki.nesdev.com/w/index.php/APU_Mixer
+// levels of pulse 1, pulse 2, triangle, noise (0-15) and DMC (0-127); the result is 0.0-1.0
+pub fn mix(levels: [f32; 5]) -> f32 {
+    let [pulse1, pulse2, triangle, noise, dmc] = levels;
+    let pulse = pulse1 + pulse2;
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+    let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+    pulse_out + tnd_out
+}
+
+// mixer output of one pulse channel at volume 15, expansion audio is scaled against it
+const PULSE_FULL_SCALE: f32 = 95.88 / (8128.0 / 15.0 + 100.0);
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelConfig {
+    pub volume: f32, // 1.0 is the console's level
+    pub muted: bool,
+    pub pan: f32, // -1.0 (left) to 1.0 (right), only used in stereo
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+            pan: 0.0,
+        }
+    }
+}
+
+impl ChannelConfig {
+    // side: None when mixing mono
+    fn gain(&self, side: Option<Side>) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        let pan = match side {
+            None => 1.0,
+            Some(Side::Left) => (1.0 - self.pan).min(1.0),
+            Some(Side::Right) => (1.0 + self.pan).min(1.0),
+        };
+        self.volume * pan
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioConfig {
+    pub pulse1: ChannelConfig,
+    pub pulse2: ChannelConfig,
+    pub triangle: ChannelConfig,
+    pub noise: ChannelConfig,
+    pub dmc: ChannelConfig,
+    pub expansion: ChannelConfig, // the cartridge's sound chip, if any
+    // the chip's full scale as a multiple of a 2A03 pulse, None for ExpansionChip::relative_level
+    pub expansion_level: Option<f32>,
+    pub stereo: bool, // samples are interleaved left, right
+}
+
+impl AudioConfig {
+    // the pulses apart, the bass and drums near the middle
+    #[allow(dead_code)]
+    pub fn stereo_spread() -> Self {
+        let panned = |pan| ChannelConfig {
+            pan,
+            ..ChannelConfig::default()
+        };
+        Self {
+            pulse1: panned(-0.5),
+            pulse2: panned(0.5),
+            triangle: panned(0.0),
+            noise: panned(-0.25),
+            dmc: panned(0.25),
+            expansion: panned(0.0),
+            expansion_level: None,
+            stereo: true,
+        }
+    }
+
+    // expansion audio output (0.0-1.0 of the chip's full scale) in mixer units, for one side
+    pub fn apply_expansion(
+        &self,
+        expansion: Option<(ExpansionChip, f32)>,
+        side: Option<Side>,
+    ) -> f32 {
+        match expansion {
+            Some((chip, output)) => {
+                let level = self
+                    .expansion_level
+                    .unwrap_or_else(|| chip.relative_level());
+                output * level * PULSE_FULL_SCALE * self.expansion.gain(side)
+            }
+            None => 0.0,
+        }
+    }
+
+    // channel levels (pulse 1, pulse 2, triangle, noise, DMC) scaled for one side
+    pub fn apply(&self, levels: [u8; 5], side: Option<Side>) -> [f32; 5] {
+        let channels = [
+            &self.pulse1,
+            &self.pulse2,
+            &self.triangle,
+            &self.noise,
+            &self.dmc,
+        ];
+        let mut scaled = [0.0; 5];
+        for ((scaled, level), channel) in scaled.iter_mut().zip(levels.iter()).zip(channels.iter())
+        {
+            *scaled = *level as f32 * channel.gain(side);
+        }
+        scaled
+    }
+}
+
+// phases of the step kernel per output sample, and taps per phase
+const PHASES: usize = 32;
+const TAPS: usize = 16;
+
+// https://www.slack.net/~ant/bl-synth/
+// every change of the level is added as a band-limited step, so the APU can be
+// sampled at its own clock rate without aliasing
+#[derive(Debug, Clone)]
+pub struct AudioOutput {
+    clock_rate: f64,
+    sample_rate: u32,
+    time: f64, // position of the current clock in output samples, always 0.0-1.0
+    level: f32,
+    deltas: VecDeque<f32>, // level changes still to be added up, one per output sample
+    integrator: f32,
+    kernel: Vec<[f32; TAPS]>,
+    filters: [Filter; 3],
+    samples: Vec<i16>,
+}
+
+impl Default for AudioOutput {
+    fn default() -> Self {
+        Self::new(Region::default().cpu_clock_rate(), DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl AudioOutput {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        Self {
+            clock_rate,
+            sample_rate,
+            time: 0.0,
+            level: 0.0,
+            deltas: vec![0.0; TAPS + 1].into(),
+            integrator: 0.0,
+            kernel: step_kernel(),
+            filters: output_filters(sample_rate),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.filters = output_filters(sample_rate);
+    }
+
+    // called once per clock with the mixed level
+    pub fn clock(&mut self, level: f32) {
+        if level != self.level {
+            let phase = ((self.time * PHASES as f64) as usize).min(PHASES - 1);
+            let delta = level - self.level;
+            for (d, k) in self.deltas.iter_mut().zip(self.kernel[phase].iter()) {
+                *d += delta * k;
+            }
+            self.level = level;
+        }
+
+        self.time += self.sample_rate as f64 / self.clock_rate;
+        while self.time >= 1.0 {
+            self.time -= 1.0;
+            // no later step can reach the oldest sample any more
+            self.integrator += self.deltas.pop_front().unwrap_or(0.0);
+            self.deltas.push_back(0.0);
+            let sample = self
+                .filters
+                .iter_mut()
+                .fold(self.integrator, |sample, filter| filter.process(sample));
+            self.samples
+                .push((sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+        }
+        // nobody is taking samples, keep the last second at most
+        if self.samples.len() > self.sample_rate as usize {
+            let overflow = self.samples.len() - self.sample_rate as usize;
+            self.samples.drain(..overflow);
+        }
+    }
+
+    // appends the samples produced since the last call
+    pub fn take_samples(&mut self, out: &mut Vec<i16>) {
+        out.append(&mut self.samples);
+    }
+
+    pub fn discard_samples(&mut self) {
+        self.samples.clear();
+    }
+}
+
+// windowed sinc impulses, cut off a little below the output Nyquist frequency,
+// each delayed by a fraction of a sample and normalized so that a step keeps its height
+fn step_kernel() -> Vec<[f32; TAPS]> {
+    const CUTOFF: f64 = 0.45; // cycles per output sample
+    (0..PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / PHASES as f64;
+            let mut taps = [0.0; TAPS];
+            for (i, tap) in taps.iter_mut().enumerate() {
+                let x = i as f64 - (TAPS / 2) as f64 - offset;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (std::f64::consts::PI * 2.0 * CUTOFF * x).sin()
+                        / (std::f64::consts::PI * 2.0 * CUTOFF * x)
+                };
+                // Blackman window, zero half the taps away on either side
+                let n = ((x + (TAPS / 2) as f64) / TAPS as f64).clamp(0.0, 1.0);
+                let window = 0.42 - 0.5 * (2.0 * std::f64::consts::PI * n).cos()
+                    + 0.08 * (4.0 * std::f64::consts::PI * n).cos();
+                *tap = (sinc * window) as f32;
+            }
+            let sum: f32 = taps.iter().sum();
+            for tap in taps.iter_mut() {
+                *tap /= sum;
+            }
+            taps
+        })
+        .collect()
+}
+
+// https://wiki.nesdev.com/w/index.php/APU_Mixer
+// the console's output stage: two high-pass filters and a low-pass filter
+fn output_filters(sample_rate: u32) -> [Filter; 3] {
+    [
+        Filter::new(FilterKind::HighPass, 90.0, sample_rate),
+        Filter::new(FilterKind::HighPass, 440.0, sample_rate),
+        Filter::new(FilterKind::LowPass, 14000.0, sample_rate),
+    ]
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+// first-order RC filter
+#[derive(Debug, Clone, Copy)]
+struct Filter {
+    kind: FilterKind,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl Filter {
+    fn new(kind: FilterKind, cutoff: f32, sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        let alpha = match kind {
+            FilterKind::HighPass => rc / (rc + dt),
+            FilterKind::LowPass => dt / (rc + dt),
+        };
+        Self {
+            kind,
+            alpha,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = match self.kind {
+            FilterKind::HighPass => {
+                self.alpha * (self.previous_output + input - self.previous_input)
+            }
+            FilterKind::LowPass => {
+                self.previous_output + self.alpha * (input - self.previous_output)
+            }
+        };
+        self.previous_input = input;
+        self.previous_output = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expansion_level_overrides_the_chip_default() {
+        let mut config = AudioConfig::default();
+        let default = config.apply_expansion(Some((ExpansionChip::Vrc6, 1.0)), None);
+        assert_eq!(
+            default,
+            ExpansionChip::Vrc6.relative_level() * PULSE_FULL_SCALE
+        );
+
+        config.expansion_level = Some(1.0);
+        let overridden = config.apply_expansion(Some((ExpansionChip::Vrc6, 1.0)), None);
+        assert_eq!(overridden, PULSE_FULL_SCALE);
+    }
+}
@@ -1,13 +1,13 @@
 use emu6502::ram::{MemIO, RAM};
 use emu6502::reset::Reset;
 
-use crate::{controller::ControllerInput, mapper::Mapper, ppu::PPU};
+use crate::{apu::APU, controller::ControllerInput, ppu::PPU};
 
 pub struct Bus<'a> {
     wram: &'a mut RAM,
     prg_rom: &'a mut Vec<u8>,
     ppu: &'a mut PPU,
-    mapper: Mapper,
+    apu: &'a mut APU,
     controller: &'a mut ControllerInput,
 }
 
@@ -15,15 +15,15 @@ impl<'a> Bus<'a> {
     pub fn new(
         wram: &'a mut RAM,
         ppu: &'a mut PPU,
+        apu: &'a mut APU,
         prg_rom: &'a mut Vec<u8>,
-        mapper: Mapper,
         controller: &'a mut ControllerInput,
     ) -> Bus<'a> {
         Bus {
             wram,
             prg_rom,
             ppu,
-            mapper,
+            apu,
             controller,
         }
     }
@@ -35,9 +35,10 @@ impl<'a> MemIO for Bus<'a> {
             0x0000..=0x07FF => self.wram.read_byte(address),
             0x0800..=0x1FFF => self.wram.read_byte(address & 0x07FF),
             0x2000..=0x2007 => self.ppu.read_byte(address),
+            0x4015 => self.apu.read_byte(address),
             0x4016 => self.controller.read_byte(),
             0x8000..=0xFFFF => {
-                let a = self.mapper.mapping_address(address);
+                let a = self.ppu.mapper().mapping_address(address);
                 self.prg_rom[a]
             }
             _ => 0,
@@ -49,9 +50,10 @@ impl<'a> MemIO for Bus<'a> {
             0x0000..=0x07FF => self.wram.read_byte_without_effect(address),
             0x0800..=0x1FFF => self.wram.read_byte_without_effect(address & 0x07FF),
             0x2000..=0x2007 => self.ppu.read_byte_without_effect(address),
+            0x4015 => self.apu.read_byte_without_effect(address),
             0x4016 => self.controller.read_byte_without_effect(),
             0x8000..=0xFFFF => {
-                let a = self.mapper.mapping_address(address);
+                let a = self.ppu.mapper().mapping_address(address);
                 self.prg_rom[a]
             }
             _ => 0,
@@ -63,9 +65,12 @@ impl<'a> MemIO for Bus<'a> {
             0x0000..=0x07FF => self.wram.write_byte(address, byte),
             0x0800..=0x1FFF => self.wram.write_byte(address & 0x07FF, byte),
             0x2000..=0x2007 => self.ppu.write_byte(address, byte),
+            0x4000..=0x4013 | 0x4015 => self.apu.write_byte(address, byte),
+            // reads of $4017 belong to controller 2, writes to the APU frame counter
+            0x4017 => self.apu.write_byte(address, byte),
             0x4014 => self.ppu.write_byte(address, byte),
             0x4016 => self.controller.write_byte(byte),
-            0x8000..=0xFFFF => {}
+            0x4020..=0xFFFF => self.ppu.mapper_mut().write_register(address, byte),
             _ => {}
         }
     }
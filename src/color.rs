@@ -1,3 +1,5 @@
+use crate::region::Region;
+
 static COLORS: [Color; 0x40] = [
     Color(84, 84, 84),
     Color(0, 30, 116),
@@ -65,10 +67,269 @@ static COLORS: [Color; 0x40] = [
     Color(0, 0, 0),
 ];
 
+// how much a channel is darkened when another colour is emphasized
+// https://wiki.nesdev.com/w/index.php/NTSC_video#Color_Tint_Bits
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Color(pub u8, pub u8, pub u8);
 impl Color {
+    #[allow(dead_code)]
     pub fn from(src: u8) -> Color {
         COLORS[src as usize]
     }
 }
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum PaletteError {
+    InvalidLength(usize),
+}
+
+impl std::fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::InvalidLength(len) => {
+                write!(f, ".pal must be 192 or 1536 bytes, got {} bytes", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+// 512 entries indexed by a 6 bit colour and 3 bit emphasis (R, G, B from $2001 bits 5-7)
+#[derive(Debug, Clone)]
+pub struct Palette {
+    colors: Vec<Color>,
+}
+
+impl Palette {
+    pub fn get(&self, index: u16) -> Color {
+        self.colors[index as usize & 0x1FF]
+    }
+
+    // https://wiki.nesdev.com/w/index.php/.pal
+    // 192 bytes hold the 64 base colours, 1536 bytes also hold the 7 emphasised sets
+    #[allow(dead_code)]
+    pub fn from_pal(bytes: &[u8]) -> Result<Palette, PaletteError> {
+        if bytes.len() != 192 && bytes.len() != 1536 {
+            return Err(PaletteError::InvalidLength(bytes.len()));
+        }
+        let colors = bytes
+            .chunks(3)
+            .map(|rgb| Color(rgb[0], rgb[1], rgb[2]))
+            .collect::<Vec<_>>();
+        if colors.len() == 0x40 {
+            Ok(Self::with_emphasis(&colors))
+        } else {
+            Ok(Self { colors })
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PAL_video
+    // the 2C07 generates each hue 15 degrees away from the 2C02
+    pub fn for_region(region: Region) -> Palette {
+        match region {
+            Region::Ntsc | Region::Dendy => Palette::default(),
+            Region::Pal => Palette::generate(&NtscPaletteParams {
+                hue: -15.0,
+                ..NtscPaletteParams::default()
+            }),
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/NTSC_video#Converting_YIQ_to_RGB
+    // decodes one colour cycle (12 samples) of the composite signal for every entry
+    pub fn generate(params: &NtscPaletteParams) -> Palette {
+        let colors = (0..0x200)
+            .map(|index| {
+                let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+                for phase in 0..12 {
+                    let level = ntsc_signal(index, phase);
+                    let angle = std::f32::consts::PI * (phase as f32 + HUE_PHASE) / 6.0
+                        + params.hue.to_radians();
+                    y += level;
+                    i += level * angle.cos();
+                    q += level * angle.sin();
+                }
+                // demodulating halves the chroma amplitude, so I and Q are doubled back
+                let y = y / 12.0 * params.contrast + params.brightness;
+                let i = i / 6.0 * params.saturation;
+                let q = q / 6.0 * params.saturation;
+                yiq_to_rgb(y, i, q, params.gamma)
+            })
+            .collect();
+        Self { colors }
+    }
+
+    fn with_emphasis(base: &[Color]) -> Self {
+        let mut colors = Vec::with_capacity(0x200);
+        for emphasis in 0..8 {
+            for (i, c) in base.iter().enumerate() {
+                // $xE and $xF are forced black and never tinted
+                if emphasis == 0 || i & 0x0F >= 0x0E {
+                    colors.push(*c);
+                    continue;
+                }
+                // each set bit darkens the other two channels, so with all three set
+                // the whole picture is darkened
+                let attenuate = |value: u8, channel: u8| {
+                    let others = (emphasis & !channel).count_ones() as i32;
+                    (value as f32 * EMPHASIS_ATTENUATION.powi(others)) as u8
+                };
+                colors.push(Color(
+                    attenuate(c.0, 0b001),
+                    attenuate(c.1, 0b010),
+                    attenuate(c.2, 0b100),
+                ));
+            }
+        }
+        Self { colors }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::with_emphasis(&COLORS)
+    }
+}
+
+// hue: degrees added to the colour burst phase
+// gamma: gamma of the display the palette is tuned for, 2.2 leaves levels untouched
+#[derive(Debug, Clone, Copy)]
+pub struct NtscPaletteParams {
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    pub gamma: f32,
+}
+
+impl Default for NtscPaletteParams {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+// phase of the colour burst relative to the PPU's colour generator, in 1/12 cycles
+pub(crate) const HUE_PHASE: f32 = 3.9;
+
+// https://wiki.nesdev.com/w/index.php/NTSC_video#Emulating_in_C.2B.2B_code
+// composite level of a 9 bit pixel (colour + emphasis) at one of the 12 subcarrier phases,
+// scaled so that black is 0.0 and white is 1.0
+pub(crate) fn ntsc_signal(pixel: u16, phase: usize) -> f32 {
+    // voltage levels, relative to sync
+    const BLACK: f32 = 0.518;
+    const WHITE: f32 = 1.962;
+    const LEVELS: [f32; 8] = [
+        0.350, 0.518, 0.962, 1.550, // signal low
+        1.094, 1.506, 1.962, 1.962, // signal high
+    ];
+
+    let color = (pixel & 0x0F) as usize;
+    let emphasis = pixel >> 6;
+    // colours $xE and $xF are forced to level 1
+    let level = if color > 13 {
+        1
+    } else {
+        ((pixel >> 4) & 0b11) as usize
+    };
+    let mut low = LEVELS[level];
+    let mut high = LEVELS[4 + level];
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let in_color_phase = |color: usize| (color + phase) % 12 < 6;
+    let mut signal = if in_color_phase(color) { high } else { low };
+    if color < 0x0E
+        && ((emphasis & 0b001 > 0 && in_color_phase(0))
+            || (emphasis & 0b010 > 0 && in_color_phase(4))
+            || (emphasis & 0b100 > 0 && in_color_phase(8)))
+    {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+// FCC NTSC YIQ to RGB, with the display gamma applied
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32, gamma: f32) -> Color {
+    let to_u8 = |v: f32| {
+        let v = if v <= 0.0 { 0.0 } else { v.powf(2.2 / gamma) };
+        (v * 255.0).round().min(255.0) as u8
+    };
+    Color(
+        to_u8(y + 0.956 * i + 0.621 * q),
+        to_u8(y - 0.272 * i - 0.647 * q),
+        to_u8(y - 1.106 * i + 1.703 * q),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_pal_accepts_base_and_full_palettes() {
+        let base = Palette::from_pal(&[0x10; 192]).unwrap();
+        assert_eq!(base.colors.len(), 0x200);
+        let full = Palette::from_pal(&[0x20; 1536]).unwrap();
+        assert_eq!(full.colors.len(), 0x200);
+        assert_eq!(full.get(0x1FF).0, 0x20);
+    }
+
+    #[test]
+    fn emphasis_darkens_the_other_channels() {
+        let palette = Palette::default();
+        let white = COLORS[0x30];
+        let darkened =
+            |value: u8, times: i32| (value as f32 * EMPHASIS_ATTENUATION.powi(times)) as u8;
+
+        let none = palette.get(0x30);
+        assert_eq!((none.0, none.1, none.2), (white.0, white.1, white.2));
+
+        let red = palette.get(0b001 << 6 | 0x30);
+        assert_eq!(
+            (red.0, red.1, red.2),
+            (white.0, darkened(white.1, 1), darkened(white.2, 1))
+        );
+
+        let all = palette.get(0b111 << 6 | 0x30);
+        assert_eq!(
+            (all.0, all.1, all.2),
+            (
+                darkened(white.0, 2),
+                darkened(white.1, 2),
+                darkened(white.2, 2)
+            )
+        );
+        assert!(all.0 < white.0 && all.1 < white.1 && all.2 < white.2);
+    }
+
+    #[test]
+    fn pal_region_has_its_own_palette() {
+        let ntsc = Palette::for_region(Region::Ntsc).get(0x12);
+        let pal = Palette::for_region(Region::Pal).get(0x12);
+        assert_ne!((ntsc.0, ntsc.1, ntsc.2), (pal.0, pal.1, pal.2));
+    }
+
+    #[test]
+    fn from_pal_rejects_other_lengths() {
+        for len in [0, 191, 193, 1535, 1537].iter() {
+            match Palette::from_pal(&vec![0; *len]) {
+                Err(PaletteError::InvalidLength(l)) => assert_eq!(l, *len),
+                Ok(_) => panic!("{} bytes accepted", len),
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ pub struct ControllerInput {
     input: u8,
     shift: usize,
     strobe: bool,
+    was_read: bool, // since the last take_read
 }
 
 impl ControllerInput {
@@ -10,6 +11,7 @@ impl ControllerInput {
             input,
             shift: 0,
             strobe: false,
+            was_read: false,
         }
     }
 
@@ -55,7 +57,13 @@ impl ControllerInput {
         }
     }
 
+    // whether $4016 was read since the last call
+    pub fn take_read(&mut self) -> bool {
+        std::mem::take(&mut self.was_read)
+    }
+
     pub fn read_byte(&mut self) -> u8 {
+        self.was_read = true;
         let byte = self.input >> self.shift & 1;
         if !self.strobe {
             self.shift = (self.shift + 1) % 8;
@@ -0,0 +1,71 @@
+use crate::{color::Color, ppu::PPU};
+
+#[derive(Debug, Clone, Copy)]
+pub enum PatternPalette {
+    Greyscale,
+    Palette(u8), // 0-3 for background palettes, 4-7 for sprite palettes
+}
+
+// https://wiki.nesdev.com/w/index.php/PPU_pattern_tables
+// table: 0 for $0000-$0FFF, 1 for $1000-$1FFF
+// 16x16 tiles, 128x128 pixels
+pub fn render_pattern_table(ppu: &PPU, table: usize, palette: PatternPalette) -> image::RgbImage {
+    let palette_ram = ppu.palette_ram();
+    let base = (table & 1) * 0x1000;
+    let mut image = image::RgbImage::new(128, 128);
+    for tile in 0..256 {
+        let pixels = decode_tile(ppu.chr_rom(), base + tile * 0x10);
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                let color = match palette {
+                    PatternPalette::Greyscale => {
+                        let level = c * 0x55;
+                        Color(level, level, level)
+                    }
+                    PatternPalette::Palette(pal) => {
+                        let index = if *c == 0 {
+                            palette_ram[0]
+                        } else {
+                            palette_ram[(pal as usize & 0x07) * 4 + *c as usize]
+                        };
+                        ppu.palette().get(index as u16 & 0x3F)
+                    }
+                };
+                image.put_pixel(
+                    ((tile % 16) * 8 + x) as u32,
+                    ((tile / 16) * 8 + y) as u32,
+                    image::Rgb([color.0, color.1, color.2]),
+                );
+            }
+        }
+    }
+    image
+}
+
+// colour numbers (0-3) of the tile at address, rows top to bottom
+// addresses past the end of CHR read as 0
+pub fn decode_tile(chr: &[u8], address: usize) -> [[u8; 8]; 8] {
+    let mut pixels = [[0; 8]; 8];
+    for (y, row) in pixels.iter_mut().enumerate() {
+        let low = chr.get(address + y).copied().unwrap_or(0);
+        let high = chr.get(address + y + 8).copied().unwrap_or(0);
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let bit = 7 - x;
+            *pixel = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+        }
+    }
+    pixels
+}
+
+// https://wiki.nesdev.com/w/index.php/PPU_palettes
+// palette RAM as 16x2 swatches, background palettes on the top row and sprite palettes below
+pub fn render_palette_ram(ppu: &PPU, swatch_size: u32) -> image::RgbImage {
+    let palette_ram = ppu.palette_ram();
+    let mut image = image::RgbImage::new(16 * swatch_size, 2 * swatch_size);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let index = palette_ram[((y / swatch_size) * 16 + x / swatch_size) as usize];
+        let color = ppu.palette().get(index as u16 & 0x3F);
+        *pixel = image::Rgb([color.0, color.1, color.2]);
+    }
+    image
+}
@@ -0,0 +1,126 @@
+use crate::color::Palette;
+
+// receives every pixel the PPU outputs
+// index: 6 bit colour from palette RAM, emphasis bits (R, G, B) in bits 6-8
+pub trait FrameSink {
+    fn set_pixel(&mut self, x: usize, y: usize, index: u16, palette: &Palette);
+}
+
+// RGB, looked up from the PPU's palette
+impl FrameSink for [[[u8; 3]; 256]; 240] {
+    fn set_pixel(&mut self, x: usize, y: usize, index: u16, palette: &Palette) {
+        let c = palette.get(index);
+        self[y][x] = [c.0, c.1, c.2];
+    }
+}
+
+// raw palette indices, colour mapping is left to the caller (e.g. ntsc::NtscFilter)
+impl FrameSink for [[u16; 256]; 240] {
+    fn set_pixel(&mut self, x: usize, y: usize, index: u16, _palette: &Palette) {
+        self[y][x] = index;
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8888,
+    Bgra8888,
+    Rgb565,  // little endian
+    Indexed, // u16 palette index, little endian
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8888 | PixelFormat::Bgra8888 => 4,
+            PixelFormat::Rgb565 | PixelFormat::Indexed => 2,
+        }
+    }
+}
+
+// writes pixels straight into memory owned by the frontend (an image, a texture...)
+// stride: bytes from the start of one row to the next
+#[allow(dead_code)]
+pub struct FrameBuffer<'a> {
+    data: &'a mut [u8],
+    format: PixelFormat,
+    stride: usize,
+}
+
+#[allow(dead_code)]
+impl<'a> FrameBuffer<'a> {
+    pub fn new(data: &'a mut [u8], format: PixelFormat, stride: usize) -> Self {
+        let row_bytes = 256 * format.bytes_per_pixel();
+        assert!(stride >= row_bytes, "stride is shorter than a row");
+        assert!(
+            data.len() >= stride * 239 + row_bytes,
+            "buffer is too small for 256x240 pixels"
+        );
+        Self {
+            data,
+            format,
+            stride,
+        }
+    }
+
+    // rows packed back to back
+    pub fn packed(data: &'a mut [u8], format: PixelFormat) -> Self {
+        Self::new(data, format, 256 * format.bytes_per_pixel())
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+}
+
+impl<'a> FrameSink for FrameBuffer<'a> {
+    fn set_pixel(&mut self, x: usize, y: usize, index: u16, palette: &Palette) {
+        let offset = y * self.stride + x * self.format.bytes_per_pixel();
+        match self.format {
+            PixelFormat::Rgba8888 => {
+                let c = palette.get(index);
+                self.data[offset..offset + 4].copy_from_slice(&[c.0, c.1, c.2, 0xFF]);
+            }
+            PixelFormat::Bgra8888 => {
+                let c = palette.get(index);
+                self.data[offset..offset + 4].copy_from_slice(&[c.2, c.1, c.0, 0xFF]);
+            }
+            PixelFormat::Rgb565 => {
+                let c = palette.get(index);
+                let pixel = (c.0 as u16 >> 3) << 11 | (c.1 as u16 >> 2) << 5 | (c.2 as u16 >> 3);
+                self.data[offset..offset + 2].copy_from_slice(&pixel.to_le_bytes());
+            }
+            PixelFormat::Indexed => {
+                self.data[offset..offset + 2].copy_from_slice(&index.to_le_bytes());
+            }
+        }
+    }
+}
+
+// which layer produced a pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Backdrop,
+    Background,
+    Sprite(u8), // OAM index
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Self::Backdrop
+    }
+}
+
+// tile: tile number of the winning layer (0 for the backdrop)
+// palette: 0-3 for background palettes, 4-7 for sprite palettes
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelInfo {
+    pub layer: Layer,
+    pub tile: u8,
+    pub palette: u8,
+}
@@ -1,9 +1,15 @@
+pub mod apu;
+pub mod audio;
 pub mod bus;
 pub mod color;
 pub mod controller;
+pub mod debug;
+pub mod frame;
 pub mod mapper;
 pub mod nes;
+pub mod ntsc;
 pub mod ppu;
+pub mod region;
 pub mod rom;
 
 #[cfg(test)]
@@ -1,11 +1,15 @@
 // use std::{fs::File, io::Write};
 
+mod apu;
+mod audio;
 mod bus;
 mod color;
 mod controller;
+mod frame;
 mod mapper;
 mod nes;
 mod ppu;
+mod region;
 mod rom;
 
 fn main() {
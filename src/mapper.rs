@@ -15,6 +15,70 @@ impl Mapper {
             Mapper::NRom(m) => m.mapping_address(address),
         }
     }
+
+    pub fn write_register(&mut self, address: usize, byte: u8) {
+        match self {
+            Mapper::NRom(m) => m.write_register(address, byte),
+        }
+    }
+
+    pub fn ppu_fetch(&mut self, address: usize) {
+        match self {
+            Mapper::NRom(m) => m.ppu_fetch(address),
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        match self {
+            Mapper::NRom(m) => m.irq(),
+        }
+    }
+
+    pub fn clock_audio(&mut self) {
+        match self {
+            Mapper::NRom(m) => m.clock_audio(),
+        }
+    }
+
+    pub fn audio_output(&self) -> Option<(ExpansionChip, f32)> {
+        match self {
+            Mapper::NRom(m) => m.audio_output(),
+        }
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/Category:Expansion_audio
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpansionChip {
+    Vrc6,
+    Vrc7,
+    Namco163,
+    Sunsoft5B,
+    Mmc5,
+    Fds,
+}
+
+impl ExpansionChip {
+    // default full scale output as a multiple of one 2A03 pulse channel at volume 15.
+    // these are rough defaults worked out from each chip's channels, not measurements,
+    // and real boards vary, AudioConfig::expansion_level overrides them
+    pub fn relative_level(&self) -> f32 {
+        match self {
+            // two 4 bit pulses as loud as a 2A03 pulse and a 5 bit sawtooth twice as loud
+            ExpansionChip::Vrc6 => 4.0,
+            // six FM channels, summed by the chip's own DAC
+            ExpansionChip::Vrc7 => 3.0,
+            // depends on the board's mixing resistor, this is a middle value
+            ExpansionChip::Namco163 => 3.0,
+            // three square channels, each about as loud as a 2A03 pulse
+            ExpansionChip::Sunsoft5B => 3.0,
+            // two pulses identical to the 2A03's, the rarely used PCM channel isn't counted
+            ExpansionChip::Mmc5 => 2.0,
+            // one 6 bit wavetable channel at the loudest master volume
+            ExpansionChip::Fds => 2.4,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -26,6 +90,27 @@ pub enum Mirroring {
 pub trait Mapping {
     fn get_nametable_mirroring_type(&self) -> Mirroring;
     fn mapping_address(&self, address: usize) -> usize;
+
+    // CPU writes to $4020-$FFFF, where boards keep their bank and sound registers
+    fn write_register(&mut self, _address: usize, _byte: u8) {}
+
+    // every address the PPU fetches from while rendering, in order and on the dot it
+    // happens, so boards can follow A12 or count nametable fetches
+    fn ppu_fetch(&mut self, _address: usize) {}
+
+    // level of the board's IRQ line, ORed with the APU's
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // boards with a sound chip are clocked with the APU, once per CPU cycle
+    fn clock_audio(&mut self) {}
+
+    // the chip and its current output, 0.0-1.0 of its full scale
+    // the APU scales it to the chip's relative level before mixing
+    fn audio_output(&self) -> Option<(ExpansionChip, f32)> {
+        None
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
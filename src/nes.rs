@@ -1,9 +1,14 @@
 use std::time::Instant;
 
 use crate::{
+    apu::APU,
+    audio::AudioConfig,
     bus::Bus,
+    color::Palette,
     controller::{Button, ControllerInput},
-    ppu::{OAMDMAStatus, PPU},
+    frame::{FrameSink, PixelInfo},
+    ppu::{DebugOptions, NametableViewOptions, OAMDMAStatus, PPUEvent, SpriteInfo, PPU},
+    region::Region,
     rom::ROM,
 };
 use emu6502::{
@@ -14,70 +19,108 @@ use emu6502::{
 pub struct NES {
     cpu: CPU,
     ppu: PPU,
+    apu: APU,
     wram: RAM,
     rom: ROM,
     nmi: bool,
     controller: ControllerInput,
+    ppu_clock_remains: usize, // carried over PPU dots, scaled by the region's CPU cycles
+    dmc_stall: usize,         // CPU cycles left for the DMC sample fetch
+    mapper_irq: bool,         // level last seen, to log its rising edge
 }
 
 impl NES {
     pub fn new(rom: ROM) -> NES {
-        let ppu = PPU::new(rom.chr.clone(), rom.mapper);
+        let mut ppu = PPU::new(rom.chr.clone(), rom.mapper);
+        ppu.set_region(rom.region.unwrap_or_default());
+        let mut apu = APU::new();
+        apu.set_region(rom.region.unwrap_or_default());
         let mut prg = rom.prg.clone();
         let mut nes = NES {
             cpu: CPU::default(),
             ppu,
+            apu,
             wram: RAM::default(),
             rom,
             nmi: false,
             controller: ControllerInput::new(0),
+            ppu_clock_remains: 0,
+            dmc_stall: 0,
+            mapper_irq: false,
         };
         nes.cpu.reset(&mut Bus::new(
             &mut nes.wram,
             &mut nes.ppu,
+            &mut nes.apu,
             &mut prg,
-            nes.rom.mapper,
             &mut nes.controller,
         ));
         nes
     }
 
     // TODO: more consider interrupt timing
-    pub fn step(&mut self, display: &mut [[[u8; 3]; 256]; 240]) {
+    // display: RGB ([[[u8; 3]; 256]; 240]), palette indices ([[u16; 256]; 240]) or any FrameSink
+    pub fn step<S: FrameSink + ?Sized>(&mut self, display: &mut S) {
+        let region = self.ppu.region();
+        let (ppu_dots, cpu_cycles) = region.ppu_dots_per_cpu_cycle();
         let mut cycles = 0;
-        while cycles < (341 / 3) * (262 + 1) {
-            if let OAMDMAStatus::NotRunning = self.ppu.oam_dma_status() {
+        while cycles < (341 * cpu_cycles / ppu_dots) * (region.scan_lines() + 1) {
+            if self.dmc_stall > 0 {
+                // the CPU and OAM DMA are halted while the DMC fetches a sample byte
+                self.dmc_stall -= 1;
+            } else if let OAMDMAStatus::NotRunning = self.ppu.oam_dma_status() {
+                // https://wiki.nesdev.com/w/index.php/CPU_interrupts
+                // IRQ is level triggered and polled between instructions,
+                // CPU::interrupt masks it with the I flag
+                let irq = self.apu.irq() || self.mapper_irq;
+                self.ppu.set_cpu_pc(self.cpu.pc as u16);
                 let mut bus = Bus::new(
                     &mut self.wram,
                     &mut self.ppu,
+                    &mut self.apu,
                     &mut self.rom.prg,
-                    self.rom.mapper,
                     &mut self.controller,
                 );
+                if irq && self.cpu.remain_cycles == 0 {
+                    // the 7 cycle interrupt sequence runs in place of the next instruction
+                    self.cpu.interrupt(&mut bus, Interrupt::IRQ);
+                }
                 self.cpu.step(&mut bus);
-            }
-            if let OAMDMAStatus::Running(address) = self.ppu.oam_dma_status() {
+            } else if let OAMDMAStatus::Running(address) = self.ppu.oam_dma_status() {
                 let byte = {
                     let mut bus = Bus::new(
                         &mut self.wram,
                         &mut self.ppu,
+                        &mut self.apu,
                         &mut self.rom.prg,
-                        self.rom.mapper,
                         &mut self.controller,
                     );
                     bus.read_byte(address)
                 };
                 self.ppu.oam_dma_write(byte)
             }
+            let controller_read = self.controller.take_read();
+
+            self.apu.step(self.ppu.mapper_mut());
+            if let Some(address) = self.apu.dmc_dma_request() {
+                self.dmc_dma(address, controller_read);
+            }
+            let mapper_irq = self.ppu.mapper().irq();
+            if mapper_irq && !self.mapper_irq {
+                self.ppu.log_mapper_irq();
+            }
+            self.mapper_irq = mapper_irq;
 
-            for _ in 0..3 {
+            self.ppu_clock_remains += ppu_dots;
+            while self.ppu_clock_remains >= cpu_cycles {
+                self.ppu_clock_remains -= cpu_cycles;
                 self.ppu.step(display, &mut self.nmi);
                 if self.nmi {
                     let mut bus = Bus::new(
                         &mut self.wram,
                         &mut self.ppu,
+                        &mut self.apu,
                         &mut self.rom.prg,
-                        self.rom.mapper,
                         &mut self.controller,
                     );
                     self.cpu.interrupt(&mut bus, Interrupt::NMI);
@@ -96,6 +139,142 @@ impl NES {
         }
     }
 
+    // https://wiki.nesdev.com/w/index.php/APU_DMC#Memory_reader
+    // the CPU is halted for 4 cycles, or 2 when the fetch lands on an OAM DMA (1 on its last byte)
+    fn dmc_dma(&mut self, address: usize, controller_read: bool) {
+        let byte = Bus::new(
+            &mut self.wram,
+            &mut self.ppu,
+            &mut self.apu,
+            &mut self.rom.prg,
+            &mut self.controller,
+        )
+        .read_byte(address);
+        self.apu.dmc_dma_write(byte);
+        self.dmc_stall = dmc_stall_cycles(self.ppu.oam_dma_status());
+        // a $4016 read the CPU was halted on is repeated, clocking the shift register twice
+        if controller_read {
+            self.controller.read_byte();
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.ppu.region()
+    }
+
+    // host audio rate, 44100 Hz unless set
+    #[allow(dead_code)]
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    #[allow(dead_code)]
+    pub fn audio_config(&self) -> AudioConfig {
+        self.apu.audio_config()
+    }
+
+    // per-channel volume, mute and panning
+    #[allow(dead_code)]
+    pub fn set_audio_config(&mut self, config: AudioConfig) {
+        self.apu.set_audio_config(config);
+    }
+
+    // appends the samples produced since the last call, usually once per step
+    // mono, or interleaved left and right when AudioConfig::stereo is set
+    #[allow(dead_code)]
+    pub fn take_audio(&mut self, out: &mut Vec<i16>) {
+        self.apu.take_audio(out);
+    }
+
+    // overrides the region detected from the ROM header, along with its palette
+    #[allow(dead_code)]
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.ppu_clock_remains = 0;
+    }
+
+    // decoded view of the 64 OAM entries
+    #[allow(dead_code)]
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        self.ppu.sprites()
+    }
+
+    #[allow(dead_code)]
+    pub fn render_nametables(&mut self, options: NametableViewOptions) -> image::RgbImage {
+        self.ppu.render_nametables(options)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_event_log_enabled(&mut self, enabled: bool) {
+        self.ppu.set_event_log_enabled(enabled);
+    }
+
+    // PPU register accesses, NMIs and sprite 0 hits of the last completed frame
+    #[allow(dead_code)]
+    pub fn events(&self) -> &[PPUEvent] {
+        self.ppu.events()
+    }
+
+    // subcarrier phase of each line of the frame sink, for ntsc::NtscFilter
+    #[allow(dead_code)]
+    pub fn line_phases(&self) -> &[u8; 240] {
+        self.ppu.line_phases()
+    }
+
+    #[allow(dead_code)]
+    pub fn debug_options(&self) -> DebugOptions {
+        self.ppu.debug_options()
+    }
+
+    #[allow(dead_code)]
+    pub fn set_debug_options(&mut self, options: DebugOptions) {
+        self.ppu.set_debug_options(options);
+    }
+
+    // read-only access for the debug viewers
+    #[allow(dead_code)]
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    #[allow(dead_code)]
+    pub fn set_layer_frame_enabled(&mut self, enabled: bool) {
+        self.ppu.set_layer_frame_enabled(enabled);
+    }
+
+    // layer, tile and palette of every pixel of the last frame, if enabled
+    #[allow(dead_code)]
+    pub fn layer_frame(&self) -> Option<&[[PixelInfo; 256]; 240]> {
+        self.ppu.layer_frame()
+    }
+
+    // e.g. Palette::from_pal or Palette::generate
+    #[allow(dead_code)]
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.ppu.set_palette(palette);
+    }
+
+    #[allow(dead_code)]
+    pub fn frame_rate(&self) -> f64 {
+        self.region().frame_rate()
+    }
+
+    // the reset button: CPU, PPU and APU are reset, RAM and VRAM are kept
+    #[allow(dead_code)]
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+        self.nmi = false;
+        self.cpu.reset(&mut Bus::new(
+            &mut self.wram,
+            &mut self.ppu,
+            &mut self.apu,
+            &mut self.rom.prg,
+            &mut self.controller,
+        ));
+    }
+
     pub fn run(&mut self, display: &mut [[[u8; 3]; 256]; 240]) {
         self.ppu.set_rom(self.rom.chr.clone(), self.rom.mapper);
         let mut total_frames = 0;
@@ -143,3 +322,60 @@ fn snapshot(&mut display: &mut [[[u8; 3]; 256]; 240], frame_count: usize) {
         .save(format!("./tmp/a_{:0>10}.png", frame_count))
         .unwrap();
 }
+
+// CPU cycles a DMC sample fetch halts for, given where OAM DMA is
+fn dmc_stall_cycles(oam_dma: OAMDMAStatus) -> usize {
+    match oam_dma {
+        OAMDMAStatus::Running(address) if address & 0xFF == 0xFF => 1,
+        OAMDMAStatus::Running(_) => 2,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom;
+
+    // NROM-128 running `program` from $8000, with an IRQ handler spinning at $9000
+    fn nes_with_program(program: &[u8]) -> NES {
+        let mut buffer = vec![0; 16 + 0x4000 + 0x2000];
+        buffer[0..6].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1]);
+        let prg = &mut buffer[16..16 + 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x1000..0x1003].copy_from_slice(&[0x4C, 0x00, 0x90]); // JMP $9000
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x90]);
+        NES::new(rom::parse(buffer))
+    }
+
+    fn run_frames(nes: &mut NES, frames: usize) {
+        let mut display = [[0u16; 256]; 240];
+        for _ in 0..frames {
+            nes.step(&mut display);
+        }
+    }
+
+    #[test]
+    fn dmc_stall_counts() {
+        assert_eq!(dmc_stall_cycles(OAMDMAStatus::NotRunning), 4);
+        assert_eq!(dmc_stall_cycles(OAMDMAStatus::Waiting), 4);
+        assert_eq!(dmc_stall_cycles(OAMDMAStatus::Running(0x0203)), 2);
+        assert_eq!(dmc_stall_cycles(OAMDMAStatus::Running(0x02FF)), 1);
+    }
+
+    #[test]
+    fn frame_irq_is_masked_after_reset() {
+        // never touches $4017, so the frame IRQ is raised but I stays set
+        let mut nes = nes_with_program(&[0x4C, 0x00, 0x80]); // JMP $8000
+        run_frames(&mut nes, 3);
+        assert!(nes.apu.irq());
+        assert!((0x8000..0x8003).contains(&nes.cpu.pc));
+    }
+
+    #[test]
+    fn frame_irq_is_taken_once_interrupts_are_enabled() {
+        let mut nes = nes_with_program(&[0x58, 0x4C, 0x01, 0x80]); // CLI; JMP $8001
+        run_frames(&mut nes, 3);
+        assert!((0x9000..0x9003).contains(&nes.cpu.pc));
+    }
+}
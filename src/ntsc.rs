@@ -0,0 +1,90 @@
+use crate::color::{ntsc_signal, yiq_to_rgb, NtscPaletteParams, HUE_PHASE};
+
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+// every PPU pixel lasts 8 samples of a 12 sample colour subcarrier cycle
+const SAMPLES_PER_PIXEL: usize = 8;
+const LINE_SAMPLES: usize = 256 * SAMPLES_PER_PIXEL;
+// each output pixel covers 4 samples, half a PPU pixel
+pub const NTSC_WIDTH: usize = LINE_SAMPLES / 4;
+
+// encodes palette indices (colour + emphasis) into a composite signal and decodes it back,
+// reproducing artifact colours, colour bleeding and dot crawl
+pub struct NtscFilter {
+    params: NtscPaletteParams,
+    // cos/sin of the decoder's carrier for each of the 12 phases
+    carrier: [(f32, f32); 12],
+}
+
+impl NtscFilter {
+    pub fn new(params: NtscPaletteParams) -> Self {
+        let mut carrier = [(0.0, 0.0); 12];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            let angle =
+                std::f32::consts::PI * (phase as f32 + HUE_PHASE) / 6.0 + params.hue.to_radians();
+            *c = (angle.cos(), angle.sin());
+        }
+        Self { params, carrier }
+    }
+
+    // indices: a frame from NES::step into a [[u16; 256]; 240]
+    // line_phases: NES::line_phases, the dot skipped on odd frames shifts them and makes
+    // the picture crawl
+    pub fn filter(
+        &self,
+        indices: &[[u16; 256]; 240],
+        line_phases: &[u8; 240],
+        out: &mut [[[u8; 3]; NTSC_WIDTH]; 240],
+    ) {
+        let mut signal = vec![0.0; LINE_SAMPLES];
+
+        for (y, line) in indices.iter().enumerate() {
+            let line_phase = line_phases[y] as usize;
+            for (x, &pixel) in line.iter().enumerate() {
+                for k in 0..SAMPLES_PER_PIXEL {
+                    let sample = x * SAMPLES_PER_PIXEL + k;
+                    signal[sample] = ntsc_signal(pixel, (line_phase + sample) % 12);
+                }
+            }
+
+            for (x, rgb) in out[y].iter_mut().enumerate() {
+                // decode one subcarrier cycle centered on this output pixel
+                let center = x * 4 + 2;
+                let start = center.saturating_sub(6);
+                let end = std::cmp::min(center + 6, LINE_SAMPLES);
+                // the window is cut short at both ends of the line, so everything is
+                // divided by the samples it really holds, and luma is taken out before
+                // demodulating so an incomplete cycle doesn't leak it into chroma
+                let window = &signal[start..end];
+                let count = window.len() as f32;
+                let luma = window.iter().sum::<f32>() / count;
+                let (mut i, mut q) = (0.0, 0.0);
+                for (offset, &level) in window.iter().enumerate() {
+                    let (cos, sin) = self.carrier[(line_phase + start + offset) % 12];
+                    i += (level - luma) * cos;
+                    q += (level - luma) * sin;
+                }
+                let luma = luma * self.params.contrast + self.params.brightness;
+                let i = i * 2.0 / count * self.params.saturation;
+                let q = q * 2.0 / count * self.params.saturation;
+                let c = yiq_to_rgb(luma, i, q, self.params.gamma);
+                *rgb = [c.0, c.1, c.2];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_ends_are_as_bright_as_the_middle() {
+        let filter = NtscFilter::new(NtscPaletteParams::default());
+        let indices = Box::new([[0x10; 256]; 240]);
+        let mut out = Box::new([[[0; 3]; NTSC_WIDTH]; 240]);
+        filter.filter(&indices, &[0; 240], &mut out);
+        let middle = out[0][NTSC_WIDTH / 2];
+        assert_eq!(out[0][0], middle);
+        assert_eq!(out[0][NTSC_WIDTH - 1], middle);
+    }
+}
@@ -1,185 +1,583 @@
 use emu6502::ram::{MemIO, RAM};
 
-use crate::color::Color;
+use crate::color::Palette;
+use crate::frame::{FrameSink, Layer, PixelInfo};
 use crate::mapper::{Mapper, Mirroring};
+use crate::region::Region;
 
 #[derive(Debug)]
 pub struct PPU {
-    mapper: Mapper,
+    mapper: Mapper, // the cartridge's one instance, the CPU bus and the APU reach it through here
+    region: Region,
 
     vram: RAM,
     palette_ram: PaletteRAM,
+    palette: Palette,
     chr_rom: Vec<u8>,
     control: Control,        // $2000
     mask: Mask,              // $2001
     status: Status,          // $2002
     oam_address: OAMAddress, // $2003
     oam_data: OAMData,       // $2004
-    scroll: Scroll,          // $2005
-    address: Address,        // $2006
-    data: Data,              // $2007
 
     oam_dma: OAMDMA, // $4014
 
+    io_latch: IOLatch,
+
+    registers: InternalRegisters,
+    background: BackgroundPipeline,
+
     oam: OAM,
     secondary_oam: OAM,
+    sprite_evaluation: SpriteEvaluation,
     sprite_temporary_buffer: [(u8, SpritePriority); 256],
     next_line_sprite_temporary_buffer: [(u8, SpritePriority); 256],
+    sprite_info_temporary_buffer: [(u8, u8, u8); 256], // OAM index, tile number, palette
+    next_line_sprite_info_temporary_buffer: [(u8, u8, u8); 256],
     zero_sprite_temporary_buffer: [u8; 256],
     next_line_zero_sprite_temporary_buffer: [u8; 256],
 
+    nmi_line: bool,
+    suppress_vblank: bool,
+    warm_up_remains: usize,
+
+    // what is under every pixel, only recorded when enabled
+    layer_frame: Option<Box<[[PixelInfo; 256]; 240]>>,
+
+    // only change what is shown, never what is emulated
+    debug: DebugOptions,
+    sprite_zero_hit: Option<(usize, usize)>, // this frame
+    last_sprite_zero_hit: Option<(usize, usize)>,
+
+    // register accesses and interrupts, only recorded when enabled
+    event_log: Option<Vec<PPUEvent>>, // this frame
+    last_frame_events: Vec<PPUEvent>,
+    cpu_pc: u16, // of the instruction being executed, for the event log
+
+    // https://wiki.nesdev.com/w/index.php/NTSC_video
+    // colour subcarrier phase (0-11) of the current dot, 8 phases per dot,
+    // and of the first pixel of each line, for composite filters
+    subcarrier_phase: usize,
+    line_phases: [u8; 240],
+
     cycles: usize,
     scan_line: usize,
+    frame: usize,
 }
 
 impl PPU {
     pub fn new(chr_rom: Vec<u8>, mapper: Mapper) -> Self {
-        PPU {
+        let mut ppu = PPU {
             mapper,
+            region: Region::default(),
             vram: RAM::new(vec![0; 0x4000]),
             palette_ram: PaletteRAM::new(),
+            palette: Palette::default(),
             chr_rom,
             control: Control::default(),
             mask: Mask::default(),
             status: Status::default(),
             oam_address: OAMAddress::default(),
             oam_data: OAMData::default(),
-            scroll: Scroll::default(),
-            address: Address::default(),
-            data: Data::default(),
             oam_dma: OAMDMA::default(),
+            io_latch: IOLatch::default(),
+            registers: InternalRegisters::default(),
+            background: BackgroundPipeline::default(),
             oam: OAM::new(64),
             secondary_oam: OAM::new(8),
+            sprite_evaluation: SpriteEvaluation::default(),
             sprite_temporary_buffer: [(0, SpritePriority::default()); 256],
             next_line_sprite_temporary_buffer: [(0, SpritePriority::default()); 256],
+            sprite_info_temporary_buffer: [(0, 0, 0); 256],
+            next_line_sprite_info_temporary_buffer: [(0, 0, 0); 256],
             zero_sprite_temporary_buffer: [0; 256],
             next_line_zero_sprite_temporary_buffer: [0; 256],
+            nmi_line: false,
+            suppress_vblank: false,
+            warm_up_remains: 0,
+            layer_frame: None,
+            debug: DebugOptions::default(),
+            sprite_zero_hit: None,
+            last_sprite_zero_hit: None,
+            event_log: None,
+            last_frame_events: Vec::new(),
+            cpu_pc: 0,
+            subcarrier_phase: 0,
+            line_phases: [0; 240],
             cycles: 0,
             scan_line: 0,
-        }
-    }
-
-    pub fn step(&mut self, display: &mut [[[u8; 3]; 256]; 240], nmi: &mut bool) {
+            frame: 0,
+        };
+        ppu.power_on();
+        ppu
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+    // VRAM, palette RAM and OAM keep whatever they hold
+    pub fn power_on(&mut self) {
+        self.control = Control::default();
+        self.mask = Mask::default();
+        self.status = Status::default();
+        self.oam_address = OAMAddress::default();
+        self.registers = InternalRegisters::default();
+        self.io_latch = IOLatch::default();
+        self.nmi_line = false;
+        self.suppress_vblank = false;
+        self.warm_up_remains = self.region.warm_up_dots();
+        self.cycles = 0;
+        self.scan_line = 0;
+        self.frame = 0;
+    }
+
+    // unlike power on, $2002, OAMADDR and the VRAM address (v) survive a reset
+    pub fn reset(&mut self) {
+        self.control = Control::default();
+        self.mask = Mask::default();
+        self.registers.t = 0;
+        self.registers.x = 0;
+        self.registers.w = false;
+        self.nmi_line = false;
+        self.warm_up_remains = self.region.warm_up_dots();
+        self.cycles = 0;
+        self.scan_line = 0;
+        self.frame = 0;
+    }
+
+    pub fn step<S: FrameSink + ?Sized>(&mut self, display: &mut S, nmi: &mut bool) {
+        self.poll_nmi(nmi);
+        self.fetch_background();
         self.render_pixel(display);
+        self.update_oam_address();
         self.evaluate_sprites();
-        self.update_status(display, nmi);
+        self.update_status();
         self.tick();
     }
 
     fn tick(&mut self) {
+        self.warm_up_remains = self.warm_up_remains.saturating_sub(1);
+        self.subcarrier_phase = (self.subcarrier_phase + 8) % 12;
+
+        // https://wiki.nesdev.com/w/index.php/PPU_frame_timing#Even.2FOdd_Frames
+        // on odd frames with rendering enabled, (339, 261) jumps straight to (0, 0)
+        if self.scan_line == self.region.pre_render_line()
+            && self.cycles == 339
+            && self.frame % 2 == 1
+            && self.is_rendering_enabled()
+            && self.region.skips_odd_frame_dot()
+        {
+            self.cycles = 340;
+        }
+
         self.cycles += 1;
         if self.cycles > 340 {
             self.cycles = 0;
             self.scan_line += 1;
 
-            // maybe set frame increments near by scan_line == 240
-
-            if self.scan_line > 261 {
+            if self.scan_line > self.region.pre_render_line() {
                 self.scan_line = 0;
+                self.frame += 1;
+                if let Some(events) = self.event_log.as_mut() {
+                    self.last_frame_events = std::mem::take(events);
+                }
             }
         }
     }
 
+    // https://wiki.nesdev.com/w/index.php/NMI
+    // NMI fires on the rising edge of (vblank && NMI enabled), polled one dot late so that
+    // a $2002 read right after vblank is set can still suppress it, and so that enabling
+    // NMI in $2000 during vblank fires immediately
+    fn poll_nmi(&mut self, nmi: &mut bool) {
+        let line = self.status.vblank && self.control.nmi_vblank;
+        if line && !self.nmi_line {
+            *nmi = true;
+            self.log_event(PPUEventKind::Nmi, 0);
+        }
+        self.nmi_line = line;
+    }
+
+    // ref: https://wiki.nesdev.com/w/index.php/PPU_rendering
+    fn fetch_background(&mut self) {
+        if !self.is_rendering_enabled() {
+            return;
+        }
+        if self.scan_line >= 240 && self.scan_line != self.region.pre_render_line() {
+            return;
+        }
+
+        let dot = self.cycles;
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.background.shift();
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            // each tile takes 8 dots: NT, AT, pattern low, pattern high (2 dots each)
+            match (dot - 1) % 8 {
+                0 => {
+                    self.background.reload();
+                    self.fetch_nametable_byte();
+                }
+                2 => self.fetch_attribute_byte(),
+                4 => self.fetch_pattern_byte(false),
+                6 => self.fetch_pattern_byte(true),
+                7 => self.registers.increment_coarse_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.registers.increment_y(),
+            257 => {
+                self.background.reload();
+                self.registers.copy_horizontal();
+            }
+            280..=304 if self.scan_line == self.region.pre_render_line() => {
+                self.registers.copy_vertical()
+            }
+            // unused nametable fetches (MMC5 relies on these)
+            337 | 339 => self.fetch_nametable_byte(),
+            _ => {}
+        }
+    }
+
+    fn fetch_nametable_byte(&mut self) {
+        let address = 0x2000 | (self.registers.v as usize & 0x0FFF);
+        self.background.nametable_latch = self.fetch_byte(address);
+    }
+
+    fn fetch_attribute_byte(&mut self) {
+        let v = self.registers.v as usize;
+        let address = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let attr_byte = self.fetch_byte(address);
+        // select the quadrant by coarse y bit 1 and coarse x bit 1
+        let shift = ((v >> 4) & 0x04) | (v & 0x02);
+        self.background.attribute_latch = (attr_byte >> shift) & 0b11;
+    }
+
+    fn fetch_pattern_byte(&mut self, high: bool) {
+        let fine_y = (self.registers.v as usize >> 12) & 0x07;
+        let address = self.control.get_background_pattern_table_base_address()
+            + self.background.nametable_latch as usize * 0x10
+            + fine_y
+            + if high { 8 } else { 0 };
+        let byte = self.fetch_byte(address);
+        if high {
+            self.background.pattern_high_latch = byte;
+            // latched with the pattern, the unused fetches at 337/339 overwrite the NT latch
+            self.background.tile_latch = self.background.nametable_latch;
+        } else {
+            self.background.pattern_low_latch = byte;
+        }
+    }
+
+    // every rendering fetch goes through here, as it does on the real PPU bus
+    fn fetch_byte(&mut self, address: usize) -> u8 {
+        self.mapper.ppu_fetch(address);
+        match address {
+            0x0000..=0x1FFF => self.chr_rom[address],
+            0x2000..=0x3EFF => self.read_byte_from_nametable(address),
+            _ => self.palette_ram.read_byte(address & 0x1F),
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_registers#OAMADDR
+    fn update_oam_address(&mut self) {
+        if !self.is_rendering_line() {
+            return;
+        }
+        // a non-zero OAMADDR at the start of rendering copies its 8 byte row over sprites 0 and 1
+        if self.scan_line == self.region.pre_render_line()
+            && self.cycles == 1
+            && self.oam_address.addr >= 8
+        {
+            let base = (self.oam_address.addr & 0xF8) as usize;
+            for i in 0..8 {
+                let byte = self.oam.read_byte(base + i);
+                self.oam.write_byte(i, byte);
+            }
+        }
+        if (257..=320).contains(&self.cycles) {
+            self.oam_address.reset();
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    // $2004 reads expose what the sprite circuitry is looking at
+    fn get_oam_data_while_rendering(&mut self) -> u8 {
+        match self.cycles {
+            // secondary OAM clear reads $FF
+            1..=64 => 0xFF,
+            // the byte evaluation last read from OAM, which it copies on the next dot
+            65..=256 => self.sprite_evaluation.byte,
+            // sprite fetches walk secondary OAM: y, tile, attribute, x then x again
+            257..=320 => {
+                let n = (self.cycles - 257) / 8;
+                let offset = std::cmp::min((self.cycles - 257) % 8, 3);
+                self.secondary_oam.read_byte(n * 4 + offset)
+            }
+            _ => self.secondary_oam.read_byte(0),
+        }
+    }
+
     // ref: https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
     fn evaluate_sprites(&mut self) {
-        if self.scan_line >= 240 {
+        let visible = self.scan_line < 240;
+        if !self.is_rendering_enabled() {
+            // nothing is evaluated or fetched, so turning rendering back on shows no sprites
+            // until the next evaluation instead of a stale line's
+            if visible && (self.cycles == 64 || self.cycles == 257) {
+                self.clear_sprite_buffers();
+            }
             return;
         }
+        if !visible && self.scan_line != self.region.pre_render_line() {
+            return;
+        }
+
+        match self.cycles {
+            // dots 1-64 clear secondary OAM to $FF
+            0 if visible => {
+                self.secondary_oam.initialize();
+                self.sprite_evaluation = SpriteEvaluation::default();
+            }
+            // dots 65-256: OAM is read on odd dots and copied to secondary OAM on even dots
+            65..=256 if visible && self.cycles % 2 == 1 => {
+                self.sprite_evaluation.byte = self.oam.read_byte(self.oam_address.addr as usize);
+            }
+            65..=256 if visible => self.copy_sprite_byte(),
+            // the pre-render line fetches too, but nothing is drawn on line 0
+            257..=320 => self.fetch_sprite(visible),
+            321 => {
+                self.sprite_temporary_buffer
+                    .copy_from_slice(&self.next_line_sprite_temporary_buffer);
+                self.sprite_info_temporary_buffer
+                    .copy_from_slice(&self.next_line_sprite_info_temporary_buffer);
+                self.zero_sprite_temporary_buffer
+                    .copy_from_slice(&self.next_line_zero_sprite_temporary_buffer);
+            }
+            _ => {}
+        }
+    }
 
-        // Cycles 1-64: Secondary OAM (32-byte buffer for current sprites on scanline) is initialized to $FF
-        if self.cycles == 0 {
-            self.secondary_oam.initialize();
-        } else if self.cycles == 64 {
-            // Cycles 65-256: Sprite evaluation
+    // https://wiki.nesdev.com/w/index.php/PPU_rendering#Cycles_257-320
+    // 8 dots per secondary OAM slot: two unused nametable fetches then the pattern bytes,
+    // empty slots fetch tile $FF so the mapper sees the same 16 fetches every line
+    fn fetch_sprite(&mut self, draw: bool) {
+        let slot = (self.cycles - 257) / 8;
+        let s = self.secondary_oam.get(slot);
+        let row = self.scan_line.wrapping_sub(s.y as usize) & 0x07;
+        let row = if s.attribute.vflip { 7 - row } else { row };
+        let address = self.control.get_sprites_pattern_table_base_address()
+            + s.tile_number as usize * 0x10
+            + row;
+        if self.cycles == 257 {
             self.next_line_sprite_temporary_buffer = [(0, SpritePriority::default()); 256];
             self.next_line_zero_sprite_temporary_buffer = [0; 256];
-            let mut found_count = 0;
-            let mut sprites = Vec::with_capacity(8);
-            for (id, &s) in self.oam.iter().enumerate() {
-                if s.y >= 240 {
-                    continue;
-                }
-                if ((s.y)..(s.y + 8)).contains(&(self.scan_line as u8)) {
-                    self.secondary_oam.set_sprite(found_count, s.clone());
-                    sprites.push((id, s));
-                    found_count += 1;
-                    if found_count == 7 {
-                        self.status.sprite_overflow = true;
-                        break;
-                    }
+        }
+        match (self.cycles - 257) % 8 {
+            0 | 2 => {
+                self.fetch_byte(0x2000 | (self.registers.v as usize & 0x0FFF));
+            }
+            4 => self.sprite_evaluation.pattern_low = self.fetch_byte(address),
+            6 => {
+                let high = self.fetch_byte(address + 8);
+                if draw && slot < self.sprite_evaluation.found {
+                    self.draw_sprite(slot, &s, self.sprite_evaluation.pattern_low, high);
                 }
             }
-            // rendering...
-            for (id, s) in sprites.iter() {
-                let cs = self.get_specified_in_sprite_tile(s, self.scan_line - s.y as usize);
+            _ => {}
+        }
+    }
 
-                for (i, &c) in cs.iter().enumerate() {
-                    if s.x as usize + i >= 256 {
-                        continue;
-                    }
-                    self.next_line_sprite_temporary_buffer[s.x as usize + i] = if c == 0 {
-                        self.next_line_sprite_temporary_buffer[s.x as usize + i]
-                    } else {
-                        (
-                            self.palette_ram
-                                .read_byte(((s.attribute.palette + 4) * 4 + c) as usize),
-                            s.attribute.priority,
-                        )
-                    };
-                    if *id == 0 {
-                        self.next_line_zero_sprite_temporary_buffer[s.x as usize + i] = c;
-                    }
+    // puts one fetched sprite row into the next line's buffers
+    fn draw_sprite(&mut self, slot: usize, s: &Sprite, low: u8, high: u8) {
+        let id = self.sprite_evaluation.oam_indices[slot];
+        let is_sprite_zero = slot == 0 && self.sprite_evaluation.sprite_zero;
+        for i in 0..8 {
+            let x = s.x as usize + i;
+            if x >= 256 {
+                continue;
+            }
+            let bit = if s.attribute.hflip { i } else { 7 - i };
+            let c = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+            if c != 0 {
+                self.next_line_sprite_info_temporary_buffer[x] =
+                    (id, s.tile_number, s.attribute.palette + 4);
+                self.next_line_sprite_temporary_buffer[x] = (
+                    self.palette_ram
+                        .read_byte(((s.attribute.palette + 4) * 4 + c) as usize),
+                    s.attribute.priority,
+                );
+            }
+            if is_sprite_zero {
+                self.next_line_zero_sprite_temporary_buffer[x] = c;
+            }
+        }
+    }
+
+    // one even dot of sprite evaluation, OAMADDR is n (bits 7-2) and m (bits 1-0)
+    fn copy_sprite_byte(&mut self) {
+        let byte = self.sprite_evaluation.byte;
+        let address = self.oam_address.addr as usize;
+        let (n, m) = (address >> 2, address & 0b11);
+        let in_range = (byte as usize..byte as usize + 8).contains(&self.scan_line);
+        let evaluation = &mut self.sprite_evaluation;
+        let next = if evaluation.done {
+            // every sprite has been looked at, n keeps stepping over the y bytes
+            address + 4
+        } else if evaluation.found < 8 {
+            self.secondary_oam
+                .write_byte(evaluation.found * 4 + m, byte);
+            if m == 0 {
+                if !evaluation.first_checked {
+                    evaluation.first_checked = true;
+                    evaluation.sprite_zero = in_range;
                 }
+                if in_range {
+                    evaluation.oam_indices[evaluation.found] = n as u8;
+                    address + 1
+                } else {
+                    address + 4
+                }
+            } else {
+                if m == 3 {
+                    evaluation.found += 1;
+                }
+                address + 1
             }
-        } else if self.cycles == 257 {
-            self.sprite_temporary_buffer
-                .copy_from_slice(&self.next_line_sprite_temporary_buffer);
-            self.zero_sprite_temporary_buffer
-                .copy_from_slice(&self.next_line_zero_sprite_temporary_buffer);
+        } else if in_range {
+            // a ninth sprite, the three bytes the hardware reads after it change nothing here
+            self.status.sprite_overflow = true;
+            evaluation.done = true;
+            address + 4
+        } else {
+            // the overflow bug: m is incremented along with n, so tile, attribute
+            // and x bytes are taken for y
+            ((n + 1) << 2) | ((m + 1) & 0b11)
+        };
+        // n wrapped around
+        if next > 0xFF {
+            evaluation.done = true;
         }
+        self.oam_address.addr = next as u8;
+    }
+
+    fn clear_sprite_buffers(&mut self) {
+        self.sprite_temporary_buffer = [(0, SpritePriority::default()); 256];
+        self.next_line_sprite_temporary_buffer = [(0, SpritePriority::default()); 256];
+        self.zero_sprite_temporary_buffer = [0; 256];
+        self.next_line_zero_sprite_temporary_buffer = [0; 256];
     }
 
-    fn update_status(&mut self, _display: &mut [[[u8; 3]; 256]; 240], nmi: &mut bool) {
+    fn update_status(&mut self) {
         // at leach new scan line...
         if self.cycles == 1 {
-            if self.scan_line == 241 {
-                self.status.set_vblank();
-                if self.control.nmi_vblank {
-                    *nmi = true;
+            if self.scan_line == self.region.vblank_line() {
+                if !self.suppress_vblank {
+                    self.status.set_vblank();
                 }
-            } else if self.scan_line == 261 {
+                self.suppress_vblank = false;
+            } else if self.scan_line == self.region.pre_render_line() {
                 self.status.clear_vblank();
                 self.status.clear_zero_hit();
+                self.status.sprite_overflow = false;
+                self.last_sprite_zero_hit = self.sprite_zero_hit.take();
             }
         }
     }
 
-    fn render_pixel(&mut self, display: &mut [[[u8; 3]; 256]; 240]) {
+    fn render_pixel<S: FrameSink + ?Sized>(&mut self, display: &mut S) {
         if self.cycles >= 257 || self.scan_line >= 240 || self.cycles == 0 {
             return;
         }
 
         let x = self.cycles - 1;
         let y = self.scan_line;
+        if x == 0 {
+            self.line_phases[y] = self.subcarrier_phase as u8;
+        }
 
-        let (sprite_c, priority) = self.sprite_temporary_buffer[x];
-        let (background_c, is_palett_enum_zero) = self.get_background_pixel(x, y);
+        let (sprite_c, priority) = if !self.mask.sprite || (x < 8 && !self.mask.sprite_left_column)
+        {
+            (0, SpritePriority::Back)
+        } else {
+            self.sprite_temporary_buffer[x]
+        };
+        let (background_c, is_palett_enum_zero) =
+            if !self.mask.background || (x < 8 && !self.mask.background_left_column) {
+                (self.palette_ram.read_byte(0), true)
+            } else {
+                self.get_background_pixel()
+            };
+
+        // debug toggles, the values above still decide sprite 0 hits
+        let (shown_sprite_c, shown_priority) = if self.debug.hide_sprites {
+            (0, SpritePriority::Back)
+        } else if self.debug.sprites_in_front && sprite_c != 0 {
+            (sprite_c, SpritePriority::Front)
+        } else {
+            (sprite_c, priority)
+        };
+        let (shown_background_c, shown_palett_enum_zero) = if self.debug.hide_background {
+            (self.palette_ram.read_byte(0), true)
+        } else {
+            (background_c, is_palett_enum_zero)
+        };
 
-        let c_byte = match priority {
-            SpritePriority::Back => {
-                if is_palett_enum_zero && sprite_c != 0 {
-                    sprite_c
-                } else {
-                    background_c
+        let c_byte = if !self.is_rendering_enabled() {
+            self.get_backdrop_color()
+        } else {
+            match shown_priority {
+                SpritePriority::Back => {
+                    if shown_palett_enum_zero && shown_sprite_c != 0 {
+                        shown_sprite_c
+                    } else {
+                        shown_background_c
+                    }
                 }
+                SpritePriority::Front => shown_sprite_c,
             }
-            SpritePriority::Front => sprite_c,
         };
 
-        let c = Color::from(c_byte);
-        display[y][x][0] = c.0;
-        display[y][x][1] = c.1;
-        display[y][x][2] = c.2;
+        let mut emphasis = self.mask.get_emphasis();
+        if self.region.swaps_red_green_emphasis() {
+            emphasis = (emphasis & 0b100) | (emphasis & 0b010) >> 1 | (emphasis & 0b001) << 1;
+        }
+        let mut index = (c_byte & self.mask.get_greyscale_mask()) as u16 | (emphasis as u16) << 6;
+        // a 9x9 box around where the hit happened last frame
+        if let Some((hit_x, hit_y)) = self.last_sprite_zero_hit {
+            let distance = (x as isize - hit_x as isize)
+                .abs()
+                .max((y as isize - hit_y as isize).abs());
+            if self.debug.highlight_sprite_zero && distance == 4 {
+                index = SPRITE_ZERO_OUTLINE;
+            }
+        }
+        display.set_pixel(x, y, index, &self.palette);
+
+        if self.layer_frame.is_some() {
+            let info = if !self.is_rendering_enabled() {
+                PixelInfo::default()
+            } else if sprite_c != 0 && (priority == SpritePriority::Front || is_palett_enum_zero) {
+                let (id, tile, palette) = self.sprite_info_temporary_buffer[x];
+                PixelInfo {
+                    layer: Layer::Sprite(id),
+                    tile,
+                    palette,
+                }
+            } else if !is_palett_enum_zero {
+                PixelInfo {
+                    layer: Layer::Background,
+                    tile: self.background.tile_number(self.registers.x),
+                    palette: self.background.pixel(self.registers.x).1,
+                }
+            } else {
+                PixelInfo::default()
+            };
+            if let Some(layer_frame) = self.layer_frame.as_mut() {
+                layer_frame[y][x] = info;
+            }
+        }
 
         // https://wiki.nesdev.com/w/index.php/PPU_OAM#Sprite_zero_hits
         // not (sprite 0 hit has already occurred this frame)
@@ -191,37 +589,40 @@ impl PPU {
             // opaque pixel of sprite 0 overlaps an opaque pixel of the background
             && self.zero_sprite_temporary_buffer[x] != 0
             // not (At x=0 to x=7 if the left-side clipping window is enabled (if bit 2 or bit 1 of PPUMASK is 0))
-            && !((0..=7).contains(&x) && (self.mask.sprite_left_column || self.mask.background_left_column))
+            && !((0..=7).contains(&x) && !(self.mask.sprite_left_column && self.mask.background_left_column))
             // not (At any pixel where the background or sprite pixel is transparent)
-            && !(background_c == 0)
+            && !is_palett_enum_zero
         {
             self.status.set_zero_hit();
+            self.sprite_zero_hit = Some((x, y));
+            self.log_event(PPUEventKind::SpriteZeroHit, 0);
         }
     }
 
-    // returns color number and palette number is 0 (transparent)
-    fn get_background_pixel(&mut self, x: usize, y: usize) -> (u8, bool) {
-        let mut nametable_address_offset = 0;
-        let mut x = x + self.scroll.x as usize;
-        let mut y = y + self.scroll.y as usize;
-        if x > 0xFF {
-            x %= 0x100;
-            nametable_address_offset += 0x400;
-        }
-        // maybe ?
-        if y > 0xEF {
-            y %= 0xF0;
-            nametable_address_offset += 0x800;
-        }
-
-        let nametable_number = x / 0x08 + (y / 0x08) * 0x20;
-        let tile_address = self.get_mirrored_name_space_address(
-            self.control.get_nametable_base_address() + nametable_address_offset + nametable_number,
-        );
-        let tile_number = self.vram[tile_address];
+    fn is_rendering_enabled(&self) -> bool {
+        self.mask.background || self.mask.sprite
+    }
+
+    // visible and pre-render scan lines with rendering on
+    fn is_rendering_line(&self) -> bool {
+        self.is_rendering_enabled()
+            && (self.scan_line < 240 || self.scan_line == self.region.pre_render_line())
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_palettes#The_background_palette_hack
+    // while rendering is off the backdrop is shown, or the palette entry v points at
+    fn get_backdrop_color(&mut self) -> u8 {
+        let addr = self.registers.v as usize & 0x3FFF;
+        if addr >= 0x3F00 {
+            self.palette_ram.read_byte(addr - 0x3F00)
+        } else {
+            self.palette_ram.read_byte(0)
+        }
+    }
 
-        let c = self.get_specified_in_tile(tile_number, x % 8, y % 8);
-        let pal = self.get_palette_number(nametable_number, nametable_address_offset);
+    // returns color number and palette number is 0 (transparent)
+    fn get_background_pixel(&mut self) -> (u8, bool) {
+        let (c, pal) = self.background.pixel(self.registers.x);
 
         (
             if c == 0 {
@@ -233,97 +634,229 @@ impl PPU {
         )
     }
 
-    #[allow(dead_code)]
-    fn get_tile(&mut self, tile_number: u8) -> Vec<Vec<u8>> {
-        let start_addr =
-            self.control.get_background_pattern_table_base_address() + tile_number as usize * 0x10;
-        let bytes = [start_addr]
+    // nametable_number: tile index in the nametable, 0-959
+    // nametable_address: $2000, $2400, $2800 or $2C00
+    fn get_palette_number(&mut self, nametable_number: usize, nametable_address: usize) -> u8 {
+        let attr_addr_lower = (nametable_number & 0x1F) / 4;
+        let attr_addr_higher = (nametable_number / 0x20) / 4;
+        let attr_addr = attr_addr_lower + attr_addr_higher * 8;
+        let attr_address =
+            self.get_mirrored_name_space_address((attr_addr as usize) + nametable_address + 0x3C0);
+        let attr_byte = self.read_byte_from_nametable(attr_address);
+        let low_addr = (nametable_number % 4) / 2;
+        let high_addr = ((nametable_number / 0x20) % 4) / 2;
+        let specific_bits = low_addr + high_addr * 2;
+
+        match specific_bits {
+            0 => attr_byte & 0b00000011,
+            1 => (attr_byte & 0b00001100) >> 2,
+            2 => (attr_byte & 0b00110000) >> 4,
+            3 => (attr_byte & 0b11000000) >> 6,
+            _ => panic!("wrong calc!"),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        // writes to $2000, $2001, $2005 and $2006 stay ignored for the new region's warm-up
+        let elapsed =
+            self.region.warm_up_dots() - self.warm_up_remains.min(self.region.warm_up_dots());
+        self.warm_up_remains = region.warm_up_dots().saturating_sub(elapsed);
+        self.region = region;
+        self.palette = Palette::for_region(region);
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_OAM
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        let height = if self.control.sprites_size { 16 } else { 8 };
+        self.oam
             .iter()
-            .cycle()
-            .take(0x10)
             .enumerate()
-            .map(|(i, addr)| self.read_byte(*addr as usize + i))
-            .collect::<Vec<_>>();
-
-        let mut tile = Vec::with_capacity(8);
-        for i in 0..8 {
-            let byte1 = bytes[i];
-            let byte2 = bytes[i + 8];
+            .map(|(index, sprite)| {
+                let pattern_address = if self.control.sprites_size {
+                    (sprite.tile_number as usize & 0x01) * 0x1000
+                        + (sprite.tile_number as usize & 0xFE) * 0x10
+                } else {
+                    self.control.get_sprites_pattern_table_base_address()
+                        + sprite.tile_number as usize * 0x10
+                };
+                let screen_y = sprite.y as usize + 1;
+                SpriteInfo {
+                    index,
+                    sprite,
+                    screen_y,
+                    height,
+                    pattern_address,
+                    visible: self.mask.sprite && screen_y < 240,
+                }
+            })
+            .collect()
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_nametables
+    // all four nametables as laid out in $2000-$2FFF, 512x480, through the current mirroring
+    pub fn render_nametables(&mut self, options: NametableViewOptions) -> image::RgbImage {
+        let mut image = image::RgbImage::new(512, 480);
+        let pattern_base = self.control.get_background_pattern_table_base_address();
+        for nametable in 0..4 {
+            let nametable_address = 0x2000 + nametable * 0x400;
+            let (left, top) = ((nametable % 2) * 256, (nametable / 2) * 240);
+            for tile_index in 0..960 {
+                let tile_number = self.read_byte_from_nametable(nametable_address + tile_index);
+                let pal = self.get_palette_number(tile_index, nametable_address);
+                let tile_address = pattern_base + tile_number as usize * 0x10;
+                for fine_y in 0..8 {
+                    let low = self.fetch_byte(tile_address + fine_y);
+                    let high = self.fetch_byte(tile_address + fine_y + 8);
+                    for fine_x in 0..8 {
+                        let bit = 7 - fine_x;
+                        let c = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                        let c_byte = if c == 0 {
+                            self.palette_ram.read_byte(0)
+                        } else {
+                            self.palette_ram.read_byte((pal * 4 + c) as usize)
+                        };
+                        let color = self.palette.get(c_byte as u16 & 0x3F);
+                        image.put_pixel(
+                            (left + (tile_index % 32) * 8 + fine_x) as u32,
+                            (top + (tile_index / 32) * 8 + fine_y) as u32,
+                            image::Rgb([color.0, color.1, color.2]),
+                        );
+                    }
+                }
+            }
+        }
 
-            let mut one_bar = Vec::with_capacity(8);
+        if options.attribute_grid {
+            // one line every 16 pixels, the area a single palette number covers
+            let grid = image::Rgb([0x40, 0x40, 0x40]);
+            for (x, y, pixel) in image.enumerate_pixels_mut() {
+                if x % 16 == 0 || (y % 240) % 16 == 0 {
+                    *pixel = grid;
+                }
+            }
+        }
 
-            for j in 0..8 {
-                one_bar
-                    .push(u8::from(byte1 & (1 << j) != 0) + u8::from(byte2 & (1 << j) != 0) << 1);
+        if options.viewport {
+            // t holds the scroll the next frame starts from
+            let t = self.registers.t as usize;
+            let scroll_x = ((t >> 10) & 1) * 256 + (t & 0x1F) * 8 + self.registers.x as usize;
+            let scroll_y = ((t >> 11) & 1) * 240 + ((t >> 5) & 0x1F) * 8 + ((t >> 12) & 0x07);
+            let outline = image::Rgb([0xFF, 0x00, 0x00]);
+            for i in 0..256 {
+                let x = ((scroll_x + i) % 512) as u32;
+                image.put_pixel(x, (scroll_y % 480) as u32, outline);
+                image.put_pixel(x, ((scroll_y + 239) % 480) as u32, outline);
+            }
+            for i in 0..240 {
+                let y = ((scroll_y + i) % 480) as u32;
+                image.put_pixel((scroll_x % 512) as u32, y, outline);
+                image.put_pixel(((scroll_x + 255) % 512) as u32, y, outline);
             }
-            tile.push(one_bar);
         }
-        tile
+        image
     }
 
-    // x: 0-7
-    // y: 0-7
-    fn get_specified_in_tile(&mut self, tile_number: u8, x: usize, y: usize) -> u8 {
-        let start_addr =
-            self.control.get_background_pattern_table_base_address() + tile_number as usize * 0x10;
+    // records a PixelInfo for every pixel, for analysis and debugging tools
+    pub fn set_layer_frame_enabled(&mut self, enabled: bool) {
+        self.layer_frame = if enabled {
+            Some(Box::new([[PixelInfo::default(); 256]; 240]))
+        } else {
+            None
+        };
+    }
 
-        let byte1 = self.read_byte(start_addr + y);
-        let byte2 = self.read_byte(start_addr + y + 8);
+    pub fn layer_frame(&self) -> Option<&[[PixelInfo; 256]; 240]> {
+        self.layer_frame.as_deref()
+    }
 
-        u8::from(byte1 & (1 << (7 - x)) != 0) + (u8::from(byte2 & (1 << (7 - x)) != 0) << 1)
+    #[allow(dead_code)]
+    pub fn palette(&self) -> &Palette {
+        &self.palette
     }
 
-    // y: 0-7
-    fn get_specified_in_sprite_tile(&mut self, s: &Sprite, y: usize) -> [u8; 8] {
-        let y = if s.attribute.vflip { 7 - y } else { y };
+    // $3F00-$3F1F with the backdrop mirrors resolved
+    #[allow(dead_code)]
+    pub fn palette_ram(&self) -> [u8; 0x20] {
+        let mut ram = [0; 0x20];
+        for (i, byte) in ram.iter_mut().enumerate() {
+            *byte = self.palette_ram.inner[self.palette_ram.mirrored_address(i)];
+        }
+        ram
+    }
 
-        let start_addr =
-            self.control.get_sprites_pattern_table_base_address() + s.tile_number as usize * 0x10;
+    #[allow(dead_code)]
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
 
-        let byte1 = self.read_byte(start_addr + y);
-        let byte2 = self.read_byte(start_addr + y + 8);
+    // records every CPU access to $2000-$2007 and $4014, NMIs and sprite 0 hits
+    pub fn set_event_log_enabled(&mut self, enabled: bool) {
+        self.event_log = if enabled { Some(Vec::new()) } else { None };
+        self.last_frame_events.clear();
+    }
 
-        let mut pixels = [0; 8];
-        for i in 0..pixels.len() {
-            let x = if s.attribute.hflip { i } else { 7 - i };
-            pixels[i] = u8::from(byte1 & (1 << x) != 0) + (u8::from(byte2 & (1 << x) != 0) << 1)
-        }
-        pixels
+    // events of the last completed frame
+    pub fn events(&self) -> &[PPUEvent] {
+        &self.last_frame_events
     }
 
-    fn get_palette_number(
-        &mut self,
-        nametable_number: usize,
-        nametable_address_offset: usize,
-    ) -> u8 {
-        let attr_addr_lower = (nametable_number & 0x1F) / 4;
-        let attr_addr_higher = (nametable_number / 0x20) / 4;
-        let attr_addr = attr_addr_lower + attr_addr_higher * 8;
-        let attr_address = self.get_mirrored_name_space_address(
-            (attr_addr as usize)
-                + self.control.get_nametable_base_address()
-                + nametable_address_offset
-                + 0x3C0,
-        );
-        let attr_byte = self.read_byte_from_nametable(attr_address);
-        let low_addr = (nametable_number % 4) / 2;
-        let high_addr = ((nametable_number / 0x20) % 4) / 2;
-        let specific_bits = low_addr + high_addr * 2;
+    // lines keep the phase of the frame they were last drawn in, so this matches the
+    // frame sink even when it holds parts of two frames
+    pub fn line_phases(&self) -> &[u8; 240] {
+        &self.line_phases
+    }
 
-        match specific_bits {
-            0 => attr_byte & 0b00000011,
-            1 => (attr_byte & 0b00001100) >> 2,
-            2 => (attr_byte & 0b00110000) >> 4,
-            3 => (attr_byte & 0b11000000) >> 6,
-            _ => panic!("wrong calc!"),
+    pub fn set_cpu_pc(&mut self, pc: u16) {
+        self.cpu_pc = pc;
+    }
+
+    fn log_event(&mut self, kind: PPUEventKind, value: u8) {
+        if let Some(events) = self.event_log.as_mut() {
+            events.push(PPUEvent {
+                kind,
+                frame: self.frame,
+                scan_line: self.scan_line,
+                cycle: self.cycles,
+                value,
+                pc: self.cpu_pc,
+            });
         }
     }
 
+    // the mapper's IRQ line went high, logged at the dot it happened
+    pub fn log_mapper_irq(&mut self) {
+        self.log_event(PPUEventKind::MapperIrq, 0);
+    }
+
+    pub fn debug_options(&self) -> DebugOptions {
+        self.debug
+    }
+
+    pub fn set_debug_options(&mut self, options: DebugOptions) {
+        self.debug = options;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
     pub fn set_rom(&mut self, rom: Vec<u8>, mapper: Mapper) {
         self.chr_rom = rom;
         self.mapper = mapper;
     }
 
+    pub fn mapper(&self) -> &Mapper {
+        &self.mapper
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut Mapper {
+        &mut self.mapper
+    }
+
     fn write_byte_to_nametable(&mut self, address: usize, byte: u8) {
         let addr = self.get_mirrored_name_space_address(address);
         self.vram.write_byte(addr, byte);
@@ -381,72 +914,126 @@ impl PPU {
 
 impl MemIO for PPU {
     fn read_byte(&mut self, address: usize) -> u8 {
-        match address {
+        let byte = match address {
             0x0000..=0x1FFF => self.chr_rom[address],
             0x2002 => {
-                let byte = self.status.get_as_u8();
+                // reading one dot before vblank is set: it reads clear and is never set this frame
+                if self.scan_line == self.region.vblank_line() && self.cycles == 1 {
+                    self.suppress_vblank = true;
+                }
+                // only bits 7-5 are driven, the rest comes from the I/O latch
+                let byte = self.status.get_as_u8() | (self.io_latch.read(self.frame) & 0x1F);
+                self.io_latch.refresh(byte, 0xE0, self.frame);
                 self.status.clear_vblank();
+                self.registers.w = false;
                 byte
             }
             0x2007 => {
-                let mut addr = self.address.get() as usize & 0x3FFF;
+                let mut addr = self.registers.v as usize & 0x3FFF;
                 if addr >= 0x3000 && addr <= 0x3EFF {
                     addr -= 0x1000;
                 }
-                let byte = match address {
+                let byte = match addr {
                     // https://wiki.nesdev.com/w/index.php/PPU_memory_map
-                    0x0000..=0x0FFF => self.chr_rom[address],
-                    0x1000..=0x1FFF => self.chr_rom[address],
-                    0x2000..=0x2FFF => self.read_byte_from_nametable(address),
-                    0x3F00..=0x3FFF => self.palette_ram.read_byte(addr - 0x3F00),
+                    0x0000..=0x0FFF => self.chr_rom[addr],
+                    0x1000..=0x1FFF => self.chr_rom[addr],
+                    0x2000..=0x2FFF => self.read_byte_from_nametable(addr),
+                    // palette entries are 6 bits wide, bits 7-6 come from the I/O latch
+                    0x3F00..=0x3FFF => {
+                        (self.palette_ram.read_byte(addr - 0x3F00) & 0x3F)
+                            | (self.io_latch.read(self.frame) & 0xC0)
+                    }
                     _ => 0,
                 };
-                self.address
+                // a palette read only drives bits 5-0
+                let driven = if addr >= 0x3F00 { 0x3F } else { 0xFF };
+                self.io_latch.refresh(byte, driven, self.frame);
+                self.registers
                     .increment_address(self.control.increment_address);
                 byte
             }
-            _ => 0,
+            0x2004 => {
+                let byte = if self.is_rendering_line() {
+                    self.get_oam_data_while_rendering()
+                } else {
+                    self.oam_data
+                        .read_byte(self.oam_address.addr as usize, &mut self.oam)
+                };
+                self.io_latch.refresh(byte, 0xFF, self.frame);
+                byte
+            }
+            // write-only registers read back the I/O latch
+            _ => self.io_latch.read(self.frame),
+        };
+        if (0x2000..=0x2007).contains(&address) {
+            self.log_event(PPUEventKind::Read(address as u16), byte);
         }
+        byte
     }
 
     fn read_byte_without_effect(&mut self, address: usize) -> u8 {
         match address {
             0x0000..=0x1FFF => self.chr_rom[address],
-            0x2002 => self.status.get_as_u8(),
-            0x2004 => self
-                .oam_data
-                .read_byte(self.oam_address.addr as usize, &mut self.oam),
+            0x2002 => self.status.get_as_u8() | (self.io_latch.value & 0x1F),
+            0x2004 => {
+                if self.is_rendering_line() {
+                    self.get_oam_data_while_rendering()
+                } else {
+                    self.oam_data
+                        .read_byte(self.oam_address.addr as usize, &mut self.oam)
+                }
+            }
             0x2007 => {
-                let mut addr = self.address.get() as usize & 0x3FFF;
+                let mut addr = self.registers.v as usize & 0x3FFF;
                 if addr >= 0x3000 && addr <= 0x3EFF {
                     addr -= 0x1000;
                 }
-                let byte = match address {
+                let byte = match addr {
                     // https://wiki.nesdev.com/w/index.php/PPU_memory_map
-                    0x0000..=0x0FFF => self.chr_rom[address],
-                    0x1000..=0x1FFF => self.chr_rom[address],
-                    0x2000..=0x2FFF => self.read_byte_from_nametable(address),
-                    0x3F00..=0x3FFF => self.palette_ram.read_byte(addr - 0x3F00),
+                    0x0000..=0x0FFF => self.chr_rom[addr],
+                    0x1000..=0x1FFF => self.chr_rom[addr],
+                    0x2000..=0x2FFF => self.read_byte_from_nametable(addr),
+                    0x3F00..=0x3FFF => {
+                        (self.palette_ram.read_byte(addr - 0x3F00) & 0x3F)
+                            | (self.io_latch.value & 0xC0)
+                    }
                     _ => 0,
                 };
                 byte
             }
-            _ => 0,
+            _ => self.io_latch.value,
         }
     }
 
     fn write_byte(&mut self, address: usize, byte: u8) {
+        if (0x2000..=0x2007).contains(&address) {
+            self.io_latch.refresh(byte, 0xFF, self.frame);
+        }
+        if (0x2000..=0x2007).contains(&address) || address == 0x4014 {
+            self.log_event(PPUEventKind::Write(address as u16), byte);
+        }
         match address {
-            0x2000 => self.control.set_as_u8(byte),
+            0x2000 | 0x2001 | 0x2005 | 0x2006 if self.warm_up_remains > 0 => {}
+            0x2000 => {
+                self.control.set_as_u8(byte);
+                self.registers.set_nametable(self.control.name_table);
+            }
             0x2001 => self.mask.set_as_u8(byte),
             0x2003 => self.oam_address.write_byte(byte),
-            0x2004 => self
-                .oam_data
-                .write_byte(self.oam_address.addr as usize, byte, &mut self.oam),
-            0x2005 => self.scroll.set_as_u8(byte),
-            0x2006 => self.address.set_as_u8(byte),
+            0x2004 => {
+                if self.is_rendering_line() {
+                    // the write is dropped, but OAMADDR still moves
+                    self.oam_address.glitch_increment();
+                } else {
+                    self.oam_data
+                        .write_byte(self.oam_address.addr as usize, byte, &mut self.oam);
+                    self.oam_address.increment();
+                }
+            }
+            0x2005 => self.registers.write_scroll(byte),
+            0x2006 => self.registers.write_address(byte),
             0x2007 => {
-                let mut addr = self.address.get() as usize & 0x3FFF;
+                let mut addr = self.registers.v as usize & 0x3FFF;
                 if addr >= 0x3000 && addr <= 0x3EFF {
                     addr -= 0x1000;
                 }
@@ -462,7 +1049,7 @@ impl MemIO for PPU {
                     }
                     _ => {}
                 };
-                self.address
+                self.registers
                     .increment_address(self.control.increment_address);
             }
             0x4014 => {
@@ -473,6 +1060,40 @@ impl MemIO for PPU {
     }
 }
 
+// https://wiki.nesdev.com/w/index.php/Open_bus_behavior#PPU_open_bus
+// each bit decays to 0 when it has not been driven for about 600ms
+const IO_LATCH_DECAY_FRAMES: usize = 36;
+
+// palette index of the sprite 0 hit outline, red
+const SPRITE_ZERO_OUTLINE: u16 = 0x16;
+
+#[derive(Default, Debug)]
+struct IOLatch {
+    value: u8,
+    refreshed_at: [usize; 8], // frame number each bit was last driven
+}
+
+impl IOLatch {
+    // drive the bits selected by mask with the bits of byte
+    fn refresh(&mut self, byte: u8, mask: u8, frame: usize) {
+        self.value = (self.value & !mask) | (byte & mask);
+        for (bit, refreshed_at) in self.refreshed_at.iter_mut().enumerate() {
+            if mask & (1 << bit) > 0 {
+                *refreshed_at = frame;
+            }
+        }
+    }
+
+    fn read(&mut self, frame: usize) -> u8 {
+        for (bit, refreshed_at) in self.refreshed_at.iter().enumerate() {
+            if frame.saturating_sub(*refreshed_at) >= IO_LATCH_DECAY_FRAMES {
+                self.value &= !(1 << bit);
+            }
+        }
+        self.value
+    }
+}
+
 // https://wiki.nesdev.com/w/index.php/PPU_palettes
 #[derive(Debug)]
 struct PaletteRAM {
@@ -540,16 +1161,6 @@ impl Control {
         self.nmi_vblank = byte & 0b10000000 > 0;
     }
 
-    pub fn get_nametable_base_address(&self) -> usize {
-        match self.name_table {
-            0b00 => 0x2000,
-            0b01 => 0x2400,
-            0b10 => 0x2800,
-            0b11 => 0x2C00,
-            _ => 0,
-        }
-    }
-
     pub fn get_sprites_pattern_table_base_address(&self) -> usize {
         if self.sprites_pattern_table {
             0x1000
@@ -591,6 +1202,22 @@ impl Mask {
         self.emphasis_green = byte & 0b01000000 > 0;
         self.emphasis_blue = byte & 0b10000000 > 0;
     }
+
+    // greyscale keeps only the luma column of the palette
+    pub fn get_greyscale_mask(&self) -> u8 {
+        if self.greyscale {
+            0x30
+        } else {
+            0x3F
+        }
+    }
+
+    // bit 0: red, bit 1: green, bit 2: blue
+    pub fn get_emphasis(&self) -> u8 {
+        (self.emphasis_red as u8)
+            | (self.emphasis_green as u8) << 1
+            | (self.emphasis_blue as u8) << 2
+    }
 }
 
 // $2002
@@ -635,6 +1262,19 @@ impl OAMAddress {
     pub fn write_byte(&mut self, byte: u8) {
         self.addr = byte;
     }
+
+    pub fn increment(&mut self) {
+        self.addr = self.addr.wrapping_add(1);
+    }
+
+    // $2004 writes during rendering bump only the high 6 bits (the sprite index)
+    pub fn glitch_increment(&mut self) {
+        self.addr = self.addr.wrapping_add(4);
+    }
+
+    pub fn reset(&mut self) {
+        self.addr = 0;
+    }
 }
 
 // $2004
@@ -651,71 +1291,176 @@ impl OAMData {
     }
 }
 
-#[derive(Debug)]
-enum ScrollNext {
-    X,
-    Y,
+// https://wiki.nesdev.com/w/index.php/PPU_scrolling
+#[derive(Default, Debug)]
+struct InternalRegisters {
+    v: u16,  // current VRAM address (15 bits)
+    t: u16,  // temporary VRAM address (15 bits)
+    x: u8,   // fine X scroll (3 bits)
+    w: bool, // first or second write toggle
 }
 
-impl Default for ScrollNext {
-    fn default() -> Self {
-        ScrollNext::X
+impl InternalRegisters {
+    // $2005
+    fn write_scroll(&mut self, byte: u8) {
+        if !self.w {
+            // t: ....... ...ABCDE <- d: ABCDE...
+            // x:              FGH <- d: .....FGH
+            self.t = (self.t & !0x001F) | (byte as u16 >> 3);
+            self.x = byte & 0b111;
+        } else {
+            // t: FGH..AB CDE..... <- d: ABCDEFGH
+            self.t =
+                (self.t & !0x73E0) | ((byte as u16 & 0b111) << 12) | ((byte as u16 & 0xF8) << 2);
+        }
+        self.w = !self.w;
     }
-}
-
-// $2005
-#[derive(Default, Debug)]
-struct Scroll {
-    x: u8,
-    y: u8,
-    next: ScrollNext,
-}
 
-impl Scroll {
-    pub fn set_as_u8(&mut self, byte: u8) {
-        match self.next {
-            ScrollNext::X => {
-                self.x = byte;
-                self.next = ScrollNext::Y;
-            }
-            ScrollNext::Y => {
-                self.y = byte;
-                self.next = ScrollNext::X;
-            }
+    // $2006
+    fn write_address(&mut self, byte: u8) {
+        if !self.w {
+            // t: .CDEFGH ........ <- d: ..CDEFGH
+            self.t = (self.t & 0x00FF) | ((byte as u16 & 0x3F) << 8);
+        } else {
+            // t: ....... ABCDEFGH <- d: ABCDEFGH
+            self.t = (self.t & 0xFF00) | byte as u16;
+            self.v = self.t;
         }
+        self.w = !self.w;
     }
-}
 
-// $2006
-#[derive(Default, Debug)]
-struct Address {
-    addr: u16,
-    is_stored_first: bool,
-}
+    // after each $2007 access
+    fn increment_address(&mut self, large_increment: bool) {
+        self.v = (self.v + if large_increment { 32 } else { 1 }) & 0x7FFF;
+    }
 
-impl Address {
-    pub fn set_as_u8(&mut self, byte: u8) {
-        if !self.is_stored_first {
-            self.addr = (byte as u16) << 8;
+    fn set_nametable(&mut self, name_table: u8) {
+        // t: ...GH.. ........ <- d: ......GH
+        self.t = (self.t & !0x0C00) | ((name_table as u16 & 0b11) << 10);
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            // switch horizontal nametable
+            self.v ^= 0x0400;
         } else {
-            self.addr += byte as u16;
+            self.v += 1;
         }
-        self.is_stored_first = !self.is_stored_first;
     }
 
-    pub fn increment_address(&mut self, large_increment: bool) {
-        self.addr += if large_increment { 32 } else { 1 }
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            // increment fine y
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            // switch vertical nametable
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // attribute table rows wrap without switching nametable
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
     }
 
-    pub fn get(&self) -> u16 {
-        self.addr
+    fn copy_horizontal(&mut self) {
+        // v: ....A.. ...BCDEF <- t: ....A.. ...BCDEF
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
     }
+
+    fn copy_vertical(&mut self) {
+        // v: GHIA.BC DEF..... <- t: GHIA.BC DEF.....
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+}
+
+// https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+// OAMADDR is where evaluation is in OAM, this holds the rest of its state
+#[derive(Default, Debug)]
+struct SpriteEvaluation {
+    byte: u8,             // read on an odd dot, copied on the next even dot
+    found: usize,         // sprites in secondary OAM
+    oam_indices: [u8; 8], // where in OAM each of them came from
+    first_checked: bool,  // the first y byte has been looked at
+    sprite_zero: bool,    // the first sprite looked at is in slot 0
+    done: bool,           // n wrapped around or a ninth sprite was found
+    pattern_low: u8,      // of the slot being fetched over dots 257-320
 }
 
-// $2007
+// latches and shift registers fed by the nametable/attribute/pattern fetches
 #[derive(Default, Debug)]
-struct Data {
-    addr: u16,
+struct BackgroundPipeline {
+    nametable_latch: u8,
+    attribute_latch: u8,
+    pattern_low_latch: u8,
+    pattern_high_latch: u8,
+    tile_latch: u8, // tile number of the latched pattern
+    pattern_low_shift: u16,
+    pattern_high_shift: u16,
+    attribute_low_shift: u16,
+    attribute_high_shift: u16,
+    // tile numbers of the high and low bytes of the shift registers
+    tile_numbers: [u8; 2],
+    shift_count: u8,
+}
+
+impl BackgroundPipeline {
+    // load the latched tile into the low 8 bits, the high 8 bits are being drawn
+    fn reload(&mut self) {
+        self.pattern_low_shift = (self.pattern_low_shift & 0xFF00) | self.pattern_low_latch as u16;
+        self.pattern_high_shift =
+            (self.pattern_high_shift & 0xFF00) | self.pattern_high_latch as u16;
+        self.attribute_low_shift = (self.attribute_low_shift & 0xFF00)
+            | if self.attribute_latch & 0b01 > 0 {
+                0xFF
+            } else {
+                0x00
+            };
+        self.attribute_high_shift = (self.attribute_high_shift & 0xFF00)
+            | if self.attribute_latch & 0b10 > 0 {
+                0xFF
+            } else {
+                0x00
+            };
+        self.tile_numbers[1] = self.tile_latch;
+    }
+
+    fn shift(&mut self) {
+        self.pattern_low_shift <<= 1;
+        self.pattern_high_shift <<= 1;
+        self.attribute_low_shift <<= 1;
+        self.attribute_high_shift <<= 1;
+        self.shift_count = (self.shift_count + 1) % 8;
+        if self.shift_count == 0 {
+            self.tile_numbers[0] = self.tile_numbers[1];
+        }
+    }
+
+    // tile number of the pixel pixel() returns
+    fn tile_number(&self, fine_x: u8) -> u8 {
+        if self.shift_count + fine_x < 8 {
+            self.tile_numbers[0]
+        } else {
+            self.tile_numbers[1]
+        }
+    }
+
+    // returns color number (0-3) and palette number (0-3)
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let mux = 0x8000 >> fine_x;
+        let c = u8::from(self.pattern_low_shift & mux > 0)
+            + (u8::from(self.pattern_high_shift & mux > 0) << 1);
+        let pal = u8::from(self.attribute_low_shift & mux > 0)
+            + (u8::from(self.attribute_high_shift & mux > 0) << 1);
+        (c, pal)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -762,14 +1507,41 @@ impl OAMDMA {
 }
 
 #[derive(Default, Debug, Clone, Copy)]
-struct Sprite {
+pub struct Sprite {
     y: u8,
     tile_number: u8,
     attribute: SpriteAttribute,
     x: u8,
 }
 
+#[allow(dead_code)]
 impl Sprite {
+    // raw OAM byte 0, one less than the first line the sprite is drawn on
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    pub fn tile_number(&self) -> u8 {
+        self.tile_number
+    }
+
+    pub fn attribute(&self) -> SpriteAttribute {
+        self.attribute
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut sprite = Sprite::default();
+        sprite.set_y(bytes[0]);
+        sprite.set_tile_number(bytes[1]);
+        sprite.set_attribute(bytes[2]);
+        sprite.set_x(bytes[3]);
+        sprite
+    }
+
     fn set_y(&mut self, y: u8) {
         self.y = y;
     }
@@ -788,7 +1560,7 @@ impl Sprite {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-enum SpritePriority {
+pub enum SpritePriority {
     Front,
     Back,
 }
@@ -809,14 +1581,32 @@ impl From<SpritePriority> for u8 {
 }
 
 #[derive(Default, Debug, Clone, Copy)]
-struct SpriteAttribute {
+pub struct SpriteAttribute {
     vflip: bool,
     hflip: bool,
     priority: SpritePriority,
     palette: u8,
 }
 
+#[allow(dead_code)]
 impl SpriteAttribute {
+    pub fn vflip(&self) -> bool {
+        self.vflip
+    }
+
+    pub fn hflip(&self) -> bool {
+        self.hflip
+    }
+
+    pub fn priority(&self) -> SpritePriority {
+        self.priority
+    }
+
+    // 0-3, drawn with palettes 4-7
+    pub fn palette(&self) -> u8 {
+        self.palette
+    }
+
     fn set_as_u8(&mut self, byte: u8) {
         self.palette = byte & 0b11;
         self.priority = match byte & 0b00100000 > 0 {
@@ -827,7 +1617,7 @@ impl SpriteAttribute {
         self.vflip = byte & 0b10000000 > 0;
     }
 
-    fn get_as_u8(&mut self) -> u8 {
+    fn get_as_u8(&self) -> u8 {
         self.palette
             + (u8::from(self.priority) << 5)
             + (u8::from(self.hflip) << 6)
@@ -835,83 +1625,341 @@ impl SpriteAttribute {
     }
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PPUEventKind {
+    Read(u16),  // register address
+    Write(u16), // register address
+    Nmi,
+    SpriteZeroHit,
+    MapperIrq,
+}
+
+// cycle and scan_line place the event on the 341 dot wide frame
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct PPUEvent {
+    pub kind: PPUEventKind,
+    pub frame: usize,
+    pub scan_line: usize,
+    pub cycle: usize,
+    pub value: u8, // byte read or written, 0 for interrupts
+    pub pc: u16,
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct DebugOptions {
+    pub hide_background: bool,
+    pub hide_sprites: bool,
+    pub sprites_in_front: bool, // ignore the behind background priority bit
+    pub highlight_sprite_zero: bool, // outline where sprite 0 hit last frame
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct NametableViewOptions {
+    pub viewport: bool,       // outline the 256x240 area the scroll registers select
+    pub attribute_grid: bool, // draw the 16x16 attribute boundaries
+}
+
+// an OAM entry as it appears on screen
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct SpriteInfo {
+    pub index: usize,
+    pub sprite: Sprite,
+    pub screen_y: usize, // OAM y + 1
+    pub height: usize,   // 8 or 16
+    // address of the top tile in the pattern tables, 8x16 sprites pick their table by bit 0
+    pub pattern_address: usize,
+    pub visible: bool,
+}
+
+// raw bytes, 4 per sprite: y, tile number, attribute, x
+// https://wiki.nesdev.com/w/index.php/PPU_OAM
 #[derive(Debug, Clone)]
 struct OAM {
-    inner: Vec<Sprite>,
+    inner: Vec<u8>,
 }
 
 impl OAM {
     fn new(capacity: usize) -> Self {
         Self {
-            inner: vec![Sprite::default(); capacity],
+            inner: vec![0; capacity * 4],
         }
     }
 
     fn initialize(&mut self) {
-        for s in self.inner.iter_mut() {
-            s.y = 0xFF;
-            s.tile_number = 0xFF;
-            s.attribute.set_as_u8(0xFF);
-            s.x = 0xFF;
+        for byte in self.inner.iter_mut() {
+            *byte = 0xFF;
         }
     }
 }
 
 impl OAM {
     fn write_byte(&mut self, address: usize, byte: u8) {
-        let idx = address / 4;
-        match address % 4 {
-            0 => {
-                self.inner[idx].set_y(byte);
-            }
-            1 => {
-                self.inner[idx].set_tile_number(byte);
-            }
-            2 => {
-                self.inner[idx].set_attribute(byte);
-            }
-            3 => {
-                self.inner[idx].set_x(byte);
-            }
-            _ => panic!("unreechable"),
-        }
+        let address = address % self.inner.len();
+        // bits 2-4 of the attribute byte do not exist and read back as 0
+        self.inner[address] = if address % 4 == 2 { byte & 0xE3 } else { byte };
     }
 
     fn read_byte(&mut self, address: usize) -> u8 {
-        let idx = address / 4;
-        match address % 4 {
-            0 => self.inner[idx].y,
-            1 => self.inner[idx].tile_number,
-            2 => self.inner[idx].attribute.get_as_u8(),
-            3 => self.inner[idx].x,
-            _ => panic!("unreechable"),
-        }
+        self.inner[address % self.inner.len()]
     }
 
-    #[allow(dead_code)]
-    fn get(&self, index: usize) -> &Sprite {
-        &self.inner[index]
+    fn get(&self, index: usize) -> Sprite {
+        Sprite::from_bytes(&self.inner[index * 4..index * 4 + 4])
     }
 
-    fn set_sprite(&mut self, index: usize, sprite: Sprite) {
-        self.inner[index] = sprite;
-    }
-
-    fn iter<'a>(&'a self) -> IterOAM<'a> {
+    fn iter(&self) -> IterOAM<'_> {
         IterOAM {
-            inner: self.inner.iter(),
+            inner: self.inner.chunks(4),
         }
     }
 }
 
 struct IterOAM<'a> {
-    inner: std::slice::Iter<'a, Sprite>,
+    inner: std::slice::Chunks<'a, u8>,
 }
 
 impl<'a> Iterator for IterOAM<'a> {
-    type Item = &'a Sprite;
+    type Item = Sprite;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+        self.inner.next().map(Sprite::from_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::NRomMapper;
+
+    fn new_ppu() -> PPU {
+        let mut ppu = PPU::new(
+            vec![0xFF; 0x2000],
+            Mapper::NRom(NRomMapper::new(1, Mirroring::Horizontal)),
+        );
+        ppu.warm_up_remains = 0;
+        ppu
+    }
+
+    fn step(ppu: &mut PPU) -> bool {
+        let mut display = [[0u16; 256]; 240];
+        let mut nmi = false;
+        ppu.step(&mut display, &mut nmi);
+        nmi
+    }
+
+    // steps until the PPU is about to run (cycles, scan_line)
+    fn step_to(ppu: &mut PPU, cycles: usize, scan_line: usize) {
+        while ppu.cycles != cycles || ppu.scan_line != scan_line {
+            step(ppu);
+        }
+    }
+
+    #[test]
+    fn status_read_just_before_vblank_suppresses_flag_and_nmi() {
+        let mut ppu = new_ppu();
+        ppu.write_byte(0x2000, 0x80);
+        let vblank_line = ppu.region.vblank_line();
+        step_to(&mut ppu, 1, vblank_line);
+        assert_eq!(ppu.read_byte(0x2002) & 0x80, 0);
+
+        let mut nmi = false;
+        while ppu.scan_line == vblank_line {
+            nmi |= step(&mut ppu);
+        }
+        assert!(!nmi);
+        assert_eq!(ppu.read_byte(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn enabling_nmi_during_vblank_raises_it_on_the_next_dot() {
+        let mut ppu = new_ppu();
+        let vblank_line = ppu.region.vblank_line();
+        step_to(&mut ppu, 10, vblank_line);
+        ppu.write_byte(0x2000, 0x80);
+        assert!(step(&mut ppu));
+        assert!(!step(&mut ppu));
+    }
+
+    #[test]
+    fn odd_frames_with_rendering_are_one_dot_shorter() {
+        let mut ppu = new_ppu();
+        ppu.write_byte(0x2001, 0b00001000);
+        let mut lengths = [0; 2];
+        for length in lengths.iter_mut() {
+            let frame = ppu.frame;
+            while ppu.frame == frame {
+                step(&mut ppu);
+                *length += 1;
+            }
+        }
+        assert_eq!(lengths, [341 * 262, 341 * 262 - 1]);
+    }
+
+    #[test]
+    fn line_phases_follow_the_skipped_dot() {
+        fn next_frame_phases(ppu: &mut PPU) -> [u8; 240] {
+            let frame = ppu.frame;
+            while ppu.frame == frame {
+                step(ppu);
+            }
+            *ppu.line_phases()
+        }
+
+        let mut ppu = new_ppu();
+        let phases = next_frame_phases(&mut ppu);
+        for y in 1..240 {
+            assert_eq!(phases[y], (phases[y - 1] + 4) % 12);
+        }
+        // 341 * 262 dots move the picture 4 phases a frame
+        let first_lines: Vec<_> = (0..3).map(|_| next_frame_phases(&mut ppu)[0]).collect();
+        assert_eq!(first_lines[1], (first_lines[0] + 4) % 12);
+        assert_eq!(first_lines[2], (first_lines[1] + 4) % 12);
+
+        // and the skipped dot brings every other frame back
+        ppu.write_byte(0x2001, 0b00001000);
+        next_frame_phases(&mut ppu);
+        let first_lines: Vec<_> = (0..3).map(|_| next_frame_phases(&mut ppu)[0]).collect();
+        assert_ne!(first_lines[0], first_lines[1]);
+        assert_eq!(first_lines[0], first_lines[2]);
+    }
+
+    #[test]
+    fn palette_reads_leave_the_upper_latch_bits_decaying() {
+        let mut ppu = new_ppu();
+        ppu.write_byte(0x2006, 0x3F);
+        ppu.write_byte(0x2006, 0x00);
+        ppu.write_byte(0x2003, 0xC0);
+        ppu.frame = IO_LATCH_DECAY_FRAMES - 1;
+        assert_eq!(ppu.read_byte(0x2007) & 0xC0, 0xC0);
+        ppu.frame = IO_LATCH_DECAY_FRAMES;
+        assert_eq!(ppu.read_byte(0x2000) & 0xC0, 0);
+    }
+
+    #[test]
+    fn sprites_are_cleared_while_rendering_is_off() {
+        let mut ppu = new_ppu();
+        ppu.write_byte(0x2003, 0);
+        for &byte in &[10, 0, 0, 0] {
+            ppu.write_byte(0x2004, byte);
+        }
+        ppu.write_byte(0x2001, 0b00010100);
+        step_to(&mut ppu, 0, 11);
+        assert!(ppu.zero_sprite_temporary_buffer[..8]
+            .iter()
+            .all(|&c| c == 3));
+
+        ppu.write_byte(0x2001, 0);
+        step_to(&mut ppu, 258, 11);
+        ppu.write_byte(0x2001, 0b00010100);
+        assert!(ppu.zero_sprite_temporary_buffer.iter().all(|&c| c == 0));
+        assert!(ppu
+            .next_line_zero_sprite_temporary_buffer
+            .iter()
+            .all(|&c| c == 0));
+    }
+
+    // sprites at the given y bytes, the rest of OAM is off screen
+    // tile numbers are $80 + the OAM index, never in range of the lines tested
+    fn ppu_with_sprites(ys: &[u8]) -> PPU {
+        let mut ppu = new_ppu();
+        ppu.write_byte(0x2003, 0);
+        for index in 0..64 {
+            let y = ys.get(index).copied().unwrap_or(0xF0);
+            for &byte in &[y, 0x80 | index as u8, 0, 0] {
+                ppu.write_byte(0x2004, byte);
+            }
+        }
+        ppu.write_byte(0x2001, 0b00010000);
+        ppu
+    }
+
+    #[test]
+    fn evaluation_steps_oam_address() {
+        let mut ppu = ppu_with_sprites(&[20]);
+        step_to(&mut ppu, 67, 20);
+        // sprite 0 is in range, so its bytes are copied one by one
+        assert_eq!(ppu.oam_address.addr, 1);
+        assert_eq!(ppu.read_byte(0x2004), 20);
+        step_to(&mut ppu, 75, 20);
+        // sprite 1 is not, so evaluation moves straight to sprite 2
+        assert_eq!(ppu.oam_address.addr, 8);
+        assert_eq!(ppu.read_byte(0x2004), 0xF0);
+        step_to(&mut ppu, 257, 20);
+        assert_eq!(ppu.sprite_evaluation.found, 1);
+        assert!(ppu.sprite_evaluation.sprite_zero);
+        step_to(&mut ppu, 258, 20);
+        assert_eq!(ppu.oam_address.addr, 0);
+    }
+
+    #[test]
+    fn ninth_sprite_sets_overflow() {
+        let mut ppu = ppu_with_sprites(&[20; 8]);
+        step_to(&mut ppu, 257, 20);
+        assert_eq!(ppu.sprite_evaluation.found, 8);
+        assert!(!ppu.status.sprite_overflow);
+
+        let mut ppu = ppu_with_sprites(&[20; 9]);
+        step_to(&mut ppu, 257, 20);
+        assert_eq!(ppu.sprite_evaluation.found, 8);
+        assert_eq!(ppu.secondary_oam.get(7).tile_number, 0x87);
+        assert!(ppu.status.sprite_overflow);
+    }
+
+    #[test]
+    fn overflow_check_reads_the_wrong_bytes() {
+        // with secondary OAM full, sprite 9's tile number is taken for its y
+        let mut ppu = ppu_with_sprites(&[20; 8]);
+        ppu.oam.write_byte(9 * 4 + 1, 20);
+        step_to(&mut ppu, 257, 20);
+        assert!(ppu.status.sprite_overflow);
+    }
+
+    #[test]
+    fn sprite_patterns_are_fetched_in_their_slot() {
+        let mut ppu = ppu_with_sprites(&[20]);
+        // slot 0 fetches its high pattern byte at dot 263
+        step_to(&mut ppu, 263, 20);
+        assert!(ppu
+            .next_line_zero_sprite_temporary_buffer
+            .iter()
+            .all(|&c| c == 0));
+        step_to(&mut ppu, 264, 20);
+        assert_eq!(ppu.next_line_zero_sprite_temporary_buffer[..8], [3; 8]);
+        // and the next line is drawn with it from dot 321
+        assert!(ppu.zero_sprite_temporary_buffer.iter().all(|&c| c == 0));
+        step_to(&mut ppu, 322, 20);
+        assert_eq!(ppu.zero_sprite_temporary_buffer[..8], [3; 8]);
+    }
+
+    #[test]
+    fn layer_frame_reports_the_drawn_tile() {
+        let mut ppu = new_ppu();
+        ppu.set_layer_frame_enabled(true);
+        ppu.write_byte(0x2006, 0x20);
+        ppu.write_byte(0x2006, 0x00);
+        for tile in 1..=32 {
+            ppu.write_byte(0x2007, tile);
+        }
+        ppu.write_byte(0x2000, 0x00);
+        ppu.write_byte(0x2005, 0);
+        ppu.write_byte(0x2005, 0);
+        ppu.write_byte(0x2001, 0b00001010);
+
+        // v is reloaded from t on the pre-render line, which also prefetches the first tiles
+        let pre_render_line = ppu.region.pre_render_line();
+        step_to(&mut ppu, 0, pre_render_line);
+        step_to(&mut ppu, 0, 240);
+        let layer_frame = ppu.layer_frame().unwrap();
+        let tiles = (0..5)
+            .map(|tile| layer_frame[0][tile * 8 + 3].tile)
+            .collect::<Vec<_>>();
+        assert_eq!(tiles, vec![1, 2, 3, 4, 5]);
+        assert!(layer_frame[0]
+            .iter()
+            .enumerate()
+            .all(|(x, info)| info.tile as usize == x / 8 + 1));
     }
 }
@@ -0,0 +1,88 @@
+// https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self::Ntsc
+    }
+}
+
+impl Region {
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#Byte_12_.28CPU.2FPPU_Timing.29
+    pub fn from_nes2_timing(byte: u8) -> Self {
+        match byte & 0b11 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            // 2 is multiple-region, which runs as NTSC
+            _ => Region::Ntsc,
+        }
+    }
+
+    pub fn scan_lines(&self) -> usize {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn pre_render_line(&self) -> usize {
+        self.scan_lines() - 1
+    }
+
+    // Dendy keeps PAL's line count but delays vblank so it takes as many cycles as NTSC
+    pub fn vblank_line(&self) -> usize {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // (PPU dots, CPU cycles): 3:1 on NTSC and Dendy, 3.2:1 on PAL
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (usize, usize) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    // CPU (and APU) clock in Hz
+    pub fn cpu_clock_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+    // PPU dots before the registers accept writes: 29658 CPU cycles on NTSC, 33132 on PAL
+    pub fn warm_up_dots(&self) -> usize {
+        match self {
+            Region::Ntsc => 29658 * 3,
+            // Dendy has PAL's line count, so it takes as many dots as PAL
+            Region::Pal | Region::Dendy => 33132 * 16 / 5,
+        }
+    }
+
+    // only the NTSC PPU drops a dot on odd frames
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        matches!(self, Region::Ntsc)
+    }
+
+    // the PAL PPU wires the red and green emphasis bits the other way round
+    pub fn swaps_red_green_emphasis(&self) -> bool {
+        matches!(self, Region::Pal)
+    }
+}
@@ -1,10 +1,12 @@
 use crate::mapper::{Mapper, Mirroring, NRomMapper};
+use crate::region::Region;
 
 #[derive(Debug, Clone)]
 pub struct ROM {
     pub prg: Vec<u8>,
     pub chr: Vec<u8>,
     pub mapper: Mapper,
+    pub region: Option<Region>, // only known for NES 2.0 headers
 }
 
 pub fn parse(rom_buffer: Vec<u8>) -> ROM {
@@ -23,9 +25,18 @@ pub fn parse(rom_buffer: Vec<u8>) -> ROM {
     };
     let mapper = Mapper::NRom(NRomMapper::new(prg_unit_count, mirroring));
 
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#Identification
+    let is_nes2 = rom_buffer[7] & 0x0C == 0x08;
+    let region = if is_nes2 {
+        Some(Region::from_nes2_timing(rom_buffer[12]))
+    } else {
+        None
+    };
+
     ROM {
         prg: rom_buffer[prg_start..chr_start].to_vec(),
         chr: rom_buffer[chr_start..chr_end].to_vec(),
         mapper,
+        region,
     }
 }
@@ -2,6 +2,7 @@ extern crate naanes;
 
 use std::{fs::File, io::Read};
 
+use naanes::frame::{FrameBuffer, PixelFormat};
 use nfd::Response;
 use piston_window::{
     clear, image as im_pis, Button, CloseEvent, EventLoop, G2dTexture, Key, PistonWindow,
@@ -14,17 +15,15 @@ fn main() {
     let rom = naanes::rom::parse(rom_buffer);
     let mut nes = naanes::nes::NES::new(rom);
 
-    let mut display_buffer: [[[u8; 3]; 256]; 240] = [[[0; 3]; 256]; 240];
-
     let scale = 2.0;
-    let mut buffer = image::ImageBuffer::new(256, 240);
+    let mut buffer: image::RgbaImage = image::ImageBuffer::new(256, 240);
 
     let mut window: PistonWindow =
         WindowSettings::new("naanes", [256 as f64 * scale, 240 as f64 * scale])
             .build()
             .unwrap();
     let mut max_fps_mode = false;
-    window.set_max_fps(60);
+    window.set_max_fps(nes.frame_rate().round() as u64);
     let mut texture_context = TextureContext {
         factory: window.factory.clone(),
         encoder: window.factory.create_command_buffer().into(),
@@ -40,17 +39,16 @@ fn main() {
     loop {
         if let Some(event) = window.next() {
             if let Some(_) = event.render_args() {
-                nes.step(&mut display_buffer);
+                nes.step(&mut FrameBuffer::packed(&mut buffer, PixelFormat::Rgba8888));
 
-                for (x, y, pixel) in buffer.enumerate_pixels_mut() {
-                    let color = display_buffer[y as usize][x as usize];
-                    let mut p = image::Rgba([color[0], color[1], color[2], 255]);
-                    if debug && ((x % 0x10) == 0 || (y % 0x10) == 0) {
-                        p[0] = p[0].wrapping_add(0x30);
-                        p[1] = p[1].wrapping_add(0x30);
-                        p[2] = p[2].wrapping_add(0x30);
+                if debug {
+                    for (x, y, p) in buffer.enumerate_pixels_mut() {
+                        if (x % 0x10) == 0 || (y % 0x10) == 0 {
+                            p[0] = p[0].wrapping_add(0x30);
+                            p[1] = p[1].wrapping_add(0x30);
+                            p[2] = p[2].wrapping_add(0x30);
+                        }
                     }
-                    *pixel = p;
                 }
 
                 texture.update(&mut texture_context, &buffer).unwrap();
@@ -92,9 +90,14 @@ fn main() {
                     Key::P => {
                         debug = !debug;
                     }
+                    Key::R => nes.reset(),
                     Key::Tab => {
                         max_fps_mode = !max_fps_mode;
-                        let fps = if max_fps_mode { 10000 } else { 68 };
+                        let fps = if max_fps_mode {
+                            10000
+                        } else {
+                            nes.frame_rate().round() as u64
+                        };
                         window.set_max_fps(fps);
                     }
                     _ => {}